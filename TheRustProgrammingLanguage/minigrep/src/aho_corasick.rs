@@ -0,0 +1,178 @@
+// Many literal patterns at once, with an Aho-Corasick automaton.
+//
+// The patterns go into a trie, and every node gets a failure link to the node
+// of the longest proper suffix of its path that is also in the trie. Following
+// those links on a mismatch means each line is read once, one character at a
+// time, however many patterns there are. With `ignore_case`, patterns and text
+// are compared after case folding.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+
+use crate::casefold;
+use crate::matches::Span;
+
+#[derive(Debug, Default)]
+struct Node {
+    next: HashMap<char, usize>,
+    fail: usize,
+    // Length in characters of the pattern that ends here, if one does
+    len: Option<usize>,
+    // The nearest node along the failure links where a pattern ends
+    output: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct AhoCorasick {
+    nodes: Vec<Node>, // the root is nodes[0]
+    ignore_case: bool,
+}
+
+impl AhoCorasick {
+    pub fn new<S: AsRef<str>>(patterns: &[S], ignore_case: bool) -> AhoCorasick {
+        let mut nodes = vec![Node::default()];
+        for pattern in patterns {
+            let mut state = 0;
+            let mut len = 0;
+            for c in pattern.as_ref().chars() {
+                let c = if ignore_case { casefold::fold(c) } else { c };
+                state = match nodes[state].next.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        nodes.push(Node::default());
+                        let next = nodes.len() - 1;
+                        nodes[state].next.insert(c, next);
+                        next
+                    }
+                };
+                len += 1;
+            }
+            nodes[state].len = Some(len);
+        }
+
+        // Breadth first, so a node's failure link is known before its children's.
+        // The children of the root fail to the root.
+        let mut queue: VecDeque<usize> = nodes[0].next.values().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let children: Vec<(char, usize)> = nodes[state].next.iter().map(|(&c, &child)| (c, child)).collect();
+            for (c, child) in children {
+                let fail = step(&nodes, nodes[state].fail, c);
+                nodes[child].fail = fail;
+                // The empty pattern is left out: it is handled by `find_spans`
+                nodes[child].output = match nodes[fail].len {
+                    Some(_) if fail != 0 => Some(fail),
+                    _ => nodes[fail].output,
+                };
+                queue.push_back(child);
+            }
+        }
+
+        AhoCorasick { nodes, ignore_case }
+    }
+
+    /// The occurrences of the patterns in `line`, without overlaps: the leftmost
+    /// one wins, and the longest among those starting at the same place.
+    pub fn find_spans(&self, line: &str) -> Vec<Span> {
+        self.find_spans_where(line, |_| true)
+    }
+
+    /// Like `find_spans`, but only among the occurrences that `keep` accepts
+    /// (e.g. whole words), which are all considered, overlapping or not.
+    pub fn find_spans_where(&self, line: &str, keep: impl Fn(Span) -> bool) -> Vec<Span> {
+        // Where each character starts, to turn pattern lengths back into bytes
+        let mut starts = Vec::new();
+        let mut found = Vec::new();
+        let mut state = 0;
+        for (i, c) in line.char_indices() {
+            starts.push(i);
+            state = step(&self.nodes, state, if self.ignore_case { casefold::fold(c) } else { c });
+
+            let node = &self.nodes[state];
+            let mut output = if node.len.is_some() && state != 0 { Some(state) } else { node.output };
+            while let Some(ending) = output {
+                let len = self.nodes[ending].len.unwrap_or(0);
+                let span = (starts[starts.len() - len], i + c.len_utf8());
+                if keep(span) {
+                    found.push(span);
+                }
+                output = self.nodes[ending].output;
+            }
+        }
+
+        found.sort_by_key(|&(start, end)| (start, Reverse(end)));
+        let mut spans: Vec<Span> = Vec::new();
+        for (start, end) in found {
+            if spans.last().is_none_or(|&(_, last_end)| start >= last_end) {
+                spans.push((start, end));
+            }
+        }
+        // Like a single empty query, an empty pattern matches every line
+        if spans.is_empty() && self.nodes[0].len.is_some() && keep((0, 0)) {
+            spans.push((0, 0));
+        }
+        spans
+    }
+}
+
+// Follows the transition for `c`, falling back along the failure links
+fn step(nodes: &[Node], mut state: usize, c: char) -> usize {
+    loop {
+        if let Some(&next) = nodes[state].next.get(&c) {
+            return next;
+        }
+        if state == 0 {
+            return 0;
+        }
+        state = nodes[state].fail;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_patterns() {
+        let automaton = AhoCorasick::new(&["he", "she", "his", "hers"], false);
+
+        assert_eq!(vec![(1, 4)], automaton.find_spans("ushers"));
+        assert_eq!(vec![(0, 3), (4, 6)], automaton.find_spans("his he"));
+        assert_eq!(vec![(0, 4)], automaton.find_spans("hers"));
+        assert_eq!(Vec::<Span>::new(), automaton.find_spans("HERS"));
+    }
+
+    #[test]
+    fn case_folding() {
+        let automaton = AhoCorasick::new(&["ΣΟΦ", "rust"], true);
+
+        assert_eq!(vec![(0, 4), (5, 11)], automaton.find_spans("Rust σοφός"));
+    }
+
+    #[test]
+    fn hundreds_of_patterns() {
+        let patterns: Vec<String> = (0..300).map(|n| format!("alert-{n}")).collect();
+        let automaton = AhoCorasick::new(&patterns, false);
+
+        assert_eq!(vec![(2, 11), (14, 21)], automaton.find_spans("x alert-150 y alert-7"));
+        assert_eq!(Vec::<Span>::new(), automaton.find_spans("alert-"));
+    }
+
+    #[test]
+    fn filtered_occurrences() {
+        let automaton = AhoCorasick::new(&["he", "she", "hers"], false);
+        let whole_word = |line: &str| automaton.find_spans_where(line, |(start, end)| {
+            (start == 0 || &line[start - 1..start] == " ") && (end == line.len() || &line[end..end + 1] == " ")
+        });
+
+        assert_eq!(vec![(1, 4), (5, 7)], automaton.find_spans("ushe he"));
+        assert_eq!(vec![(5, 7)], whole_word("ushe he"));
+        assert_eq!(vec![(0, 4)], whole_word("hers"));
+    }
+
+    #[test]
+    fn empty_patterns() {
+        assert_eq!(vec![(0, 0)], AhoCorasick::new(&["", "x"], false).find_spans("abc"));
+        assert_eq!(vec![(1, 2)], AhoCorasick::new(&["", "x"], false).find_spans("axc"));
+        assert_eq!(Vec::<Span>::new(), AhoCorasick::new(&[] as &[&str], false).find_spans("abc"));
+    }
+}
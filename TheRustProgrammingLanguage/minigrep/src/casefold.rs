@@ -0,0 +1,137 @@
+// Case-insensitive comparison with Unicode simple case folding.
+//
+// Simple folding maps every character to exactly one character, so a folded
+// query can be compared with a line character by character, without building
+// a lowercase copy of the line. For most characters the fold is their lowercase
+// form; the exceptions are the table below (from Unicode's CaseFolding.txt) and
+// characters whose lowercase form is longer than one character, like 'İ', which
+// fold to themselves.
+
+// Characters that fold to something else than their lowercase form
+const SPECIAL_FOLDS: &[(char, char)] = &[
+    ('\u{b5}', 'μ'),       // MICRO SIGN
+    ('ſ', 's'),            // LATIN SMALL LETTER LONG S
+    ('\u{345}', 'ι'),      // COMBINING GREEK YPOGEGRAMMENI
+    ('ς', 'σ'),            // GREEK SMALL LETTER FINAL SIGMA
+    ('ϐ', 'β'),
+    ('ϑ', 'θ'),
+    ('ϕ', 'φ'),
+    ('ϖ', 'π'),
+    ('ϰ', 'κ'),
+    ('ϱ', 'ρ'),
+    ('ϵ', 'ε'),
+    ('\u{1c80}', 'в'),     // CYRILLIC SMALL LETTER ROUNDED VE, and the other old forms
+    ('\u{1c81}', 'д'),
+    ('\u{1c82}', 'о'),
+    ('\u{1c83}', 'с'),
+    ('\u{1c84}', 'т'),
+    ('\u{1c85}', 'т'),
+    ('\u{1c86}', 'ъ'),
+    ('\u{1c87}', 'ѣ'),
+    ('\u{1c88}', '\u{a64b}'),
+    ('ẛ', 'ṡ'),
+    ('\u{1fbe}', 'ι'),     // GREEK PROSGEGRAMMENI
+];
+
+/// The simple case folding of `c`.
+pub fn fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    if let Ok(i) = SPECIAL_FOLDS.binary_search_by_key(&c, |&(from, _)| from) {
+        return SPECIAL_FOLDS[i].1;
+    }
+    // Cherokee folds to the uppercase letters, which came first in Unicode
+    match c as u32 {
+        0x13f8..=0x13fd => return char::from_u32(c as u32 - 8).unwrap(),
+        0xab70..=0xabbf => return char::from_u32(c as u32 - 0xab70 + 0x13a0).unwrap(),
+        0x13a0..=0x13f5 => return c,
+        _ => {}
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c, // no simple folding, e.g. 'İ' whose lowercase is "i̇"
+    }
+}
+
+/// Folds every character of `text`, for the query side of a comparison.
+pub fn fold_str(text: &str) -> Vec<char> {
+    text.chars().map(fold).collect()
+}
+
+/// If `text` starts with `folded` (compared after folding), returns the length
+/// of that prefix in bytes.
+pub fn prefix_len(text: &str, folded: &[char]) -> Option<usize> {
+    let mut len = 0;
+    let mut chars = text.chars();
+    for &expected in folded {
+        let c = chars.next()?;
+        if fold(c) != expected {
+            return None;
+        }
+        len += c.len_utf8();
+    }
+    Some(len)
+}
+
+/// Whether `line` contains `folded` anywhere.
+pub fn contains(line: &str, folded: &[char]) -> bool {
+    line.char_indices().any(|(i, _)| prefix_len(&line[i..], folded).is_some())
+        || (folded.is_empty() && line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eq(a: &str, b: &str) -> bool {
+        fold_str(a) == fold_str(b)
+    }
+
+    #[test]
+    fn special_folds_are_sorted() {
+        assert!(SPECIAL_FOLDS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn folds_beyond_lowercase() {
+        assert!(eq("ΣΟΦΟΣ", "σοφος"));
+        assert!(!eq("ΣΟΦΟΣ", "σοφoς")); // with a Latin 'o'
+        assert!(eq("σοφός", "ΣΟΦΌΣ"));
+        assert!(eq("ſtraße", "STRAẞE"));
+        assert!(eq("\u{b5}m", "μM"));
+        assert!(eq("\u{212a}elvin", "kelvin")); // KELVIN SIGN
+        assert!(eq("ꭰ", "Ꭰ")); // Cherokee
+    }
+
+    #[test]
+    fn no_multi_character_folds() {
+        // Simple folding never turns one character into several
+        assert!(!eq("straße", "STRASSE"));
+        assert_eq!('İ', fold('İ'));
+        assert!(!eq("İ", "i"));
+        // Dotless i is its own letter outside of Turkish rules
+        assert!(!eq("ı", "I"));
+    }
+
+    #[test]
+    fn multilingual_greetings() {
+        assert!(contains("Здравствуйте!", &fold_str("ЗДРАВСТВУЙТЕ")));
+        assert!(contains("Dobrý den", &fold_str("DOBRÝ")));
+        assert!(contains("Olá", &fold_str("OLÁ")));
+        assert!(contains("Hola", &fold_str("hOLA")));
+        // Scripts without case only match themselves
+        for hello in ["السلام عليكم", "שָׁלוֹם", "नमस्ते", "こんにちは", "안녕하세요", "你好"] {
+            assert!(contains(&format!("> {hello} <"), &fold_str(hello)));
+        }
+        assert!(!contains("こんにちは", &fold_str("你好")));
+    }
+
+    #[test]
+    fn prefix_lengths_are_in_the_original_text() {
+        assert_eq!(Some(6), prefix_len("ΣΟΦ and more", &fold_str("σοφ")));
+        assert_eq!(None, prefix_len("ΣΟ", &fold_str("σοφ")));
+        assert_eq!(Some(0), prefix_len("anything", &[]));
+    }
+}
@@ -0,0 +1,72 @@
+// ANSI colors for the output, in the spirit of grep's defaults:
+// matches in bold red, paths in magenta, numbers in green, separators in cyan.
+
+use std::fmt::Display;
+use std::io::{self, Write};
+
+pub const MATCH: &str = "\x1b[1;31m";
+pub const PATH: &str = "\x1b[35m";
+pub const NUMBER: &str = "\x1b[32m";
+pub const SEPARATOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    /// Turns `Auto` into `Always` or `Never`: colors only go to a terminal,
+    /// and only when `NO_COLOR` isn't set (see https://no-color.org).
+    pub fn resolve(self, is_terminal: bool, no_color: bool) -> ColorChoice {
+        match self {
+            ColorChoice::Auto if is_terminal && !no_color => ColorChoice::Always,
+            ColorChoice::Auto => ColorChoice::Never,
+            choice => choice,
+        }
+    }
+}
+
+/// Writes `text` in `style`, or as it is when `enabled` is false.
+pub fn paint(out: &mut impl Write, enabled: bool, style: &str, text: impl Display) -> io::Result<()> {
+    if enabled {
+        write!(out, "{style}{text}{RESET}")
+    } else {
+        write!(out, "{text}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_auto() {
+        assert_eq!(ColorChoice::Always, ColorChoice::Auto.resolve(true, false));
+        assert_eq!(ColorChoice::Never, ColorChoice::Auto.resolve(false, false));
+        assert_eq!(ColorChoice::Never, ColorChoice::Auto.resolve(true, true));
+        assert_eq!(ColorChoice::Always, ColorChoice::Always.resolve(false, true));
+        assert_eq!(ColorChoice::Never, ColorChoice::Never.resolve(true, false));
+    }
+
+    #[test]
+    fn paint_or_not() {
+        let mut out = Vec::new();
+        paint(&mut out, true, MATCH, "to").unwrap();
+        paint(&mut out, false, MATCH, 42).unwrap();
+
+        assert_eq!("\x1b[1;31mto\x1b[0m42", String::from_utf8(out).unwrap());
+    }
+}
@@ -0,0 +1,733 @@
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::color::ColorChoice;
+use crate::matches::Boundary;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] QUERY FILE...
+       minigrep [OPTIONS] -e QUERY... FILE...
+       minigrep [OPTIONS] -f PATTERN_FILE FILE...
+
+With FILE `-`, the standard input is searched. Several files are searched in parallel.
+
+Options:
+  -i, --ignore-case      ignore case distinctions (overrides IGNORE_CASE)
+      --no-ignore-case   match case exactly (overrides IGNORE_CASE)
+  -E, --regex            interpret QUERY as a regular expression
+  -e, --regexp=QUERY     search for QUERY; repeat to search for several at once
+  -f, --file=FILE        search for every line of FILE
+      --fuzzy=K          match QUERY with up to K typos (inserted, deleted or replaced
+                         characters); each line is prefixed with how many it took
+  -w, --word-regexp      only match whole words
+  -x, --line-regexp      only match whole lines (wins over -w)
+  -v, --invert-match     select non-matching lines
+  -n, --line-number      prefix each line with its line number
+  -b, --byte-offset      prefix each line with its byte offset in the input; for UTF-16
+                         and compressed input, the offset in the decoded UTF-8 text
+  -o, --only-matching    print only the matched parts of each line
+  -c, --count            print only the number of selected lines
+  -l, --files-with-matches print only the names of the files with selected lines
+  -L, --files-without-match print only the names of the files without selected lines
+  -m, --max-count=N      stop reading a file after N selected lines
+      --replace=TEXT     print the selected lines with every match replaced by TEXT
+      --in-place[=SUFFIX] with --replace, write the files back too (the originals kept as FILE + SUFFIX)
+  -H, --with-filename    prefix each line with the name of its file (the default with -r)
+      --json             print one JSON object per selected line (or per file with -c)
+  -Z, --null             write NUL after file names, instead of `:` or `-` (or the newline of -l and -L)
+  -A, --after-context=N  print N lines of context after each match
+  -B, --before-context=N print N lines of context before each match
+  -C, --context=N        print N lines of context around each match
+      --color[=WHEN]     highlight matches: `auto` (the default, only on a terminal), `always` or `never`
+  -r, --recursive        search the directories below FILE
+  -j, --threads=N        search with N threads (default: one per CPU)
+      --include=GLOB     with -r, only search files whose name matches GLOB
+      --exclude=GLOB     with -r, skip files and directories whose name matches GLOB
+      --hidden           with -r, search hidden files and directories too
+      --no-ignore        with -r, search the files left out by .gitignore and .ignore files too
+      --no-config        don't read the configuration file
+      --help             print this help and exit
+      --version          print the version and exit
+  --                     treat every following argument as QUERY or FILE
+
+Short flags can be combined, e.g. `-in`; their values may be attached (`-C2`).
+
+Default flags are read from the file named by MINIGREP_CONFIG (none when empty),
+or else from ~/.minigreprc: one flag per line (`--color=always`, `-C2`, `--exclude=target`),
+with blank lines and `#` comments skipped. Only flags that make sense for every search can
+be defaults: -i, --no-ignore-case, -E, -w, -x, -n, -b, -H, -Z, --color, -A, -B, -C, -r, -j,
+--include, --exclude, --hidden and --no-ignore. IGNORE_CASE is overridden by the file, and
+the file by the command line; --include and --exclude add to the values of the file.
+
+gzip and zlib compressed input, including stdin, is decompressed before searching.
+UTF-16 input (with a byte order mark, or recognised by its NUL bytes) is converted to
+UTF-8. From the first line that isn't UTF-8 on, wherever it is, input is read as Latin-1.
+A note on the standard error names the encoding assumed for such inputs.
+
+Exit status: 0 if a line was selected (with -L, if a file was listed), 1 if not, 2 on errors.";
+
+// The flags a configuration file may set: how to match, what to print around a
+// match, and which files -r searches. Patterns, modes like --replace and actions
+// like --help only make sense for one search.
+const DEFAULT_SHORT_FLAGS: &str = "iEwxnbHZABCrj";
+const DEFAULT_LONG_FLAGS: &[&str] = &[
+    "ignore-case",
+    "no-ignore-case",
+    "regex",
+    "word-regexp",
+    "line-regexp",
+    "line-number",
+    "byte-offset",
+    "with-filename",
+    "null",
+    "color",
+    "colour",
+    "after-context",
+    "before-context",
+    "context",
+    "recursive",
+    "threads",
+    "include",
+    "exclude",
+    "hidden",
+    "no-ignore",
+];
+
+// Short flags that take a value, attached or as the next argument
+const SHORT_FLAGS_WITH_VALUE: &str = "ABCjmef";
+
+pub const VERSION: &str = concat!("minigrep ", env!("CARGO_PKG_VERSION"));
+
+// What `run` should do with the parsed arguments
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Action {
+    #[default]
+    Search,
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    MissingQuery,
+    MissingFilePath,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    Requires { flag: String, requires: String },
+    Conflicts { flag: String, with: String },
+    NotAFlag(String),
+    NotADefault(String),
+    // The configuration file couldn't be read, or one of its lines is wrong
+    ConfigFile { path: String, reason: String },
+    ConfigLine { path: String, line: usize, error: Box<ConfigError> },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::MissingQuery => write!(f, "Didn't get a query string"),
+            ConfigError::MissingFilePath => write!(f, "Didn't get a file path"),
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown flag '{flag}' (see --help)"),
+            ConfigError::MissingValue(flag) => write!(f, "flag '{flag}' expects a value"),
+            ConfigError::InvalidValue { flag, value } => write!(f, "invalid value '{value}' for flag '{flag}'"),
+            ConfigError::Requires { flag, requires } => write!(f, "flag '{flag}' only works with '{requires}'"),
+            ConfigError::Conflicts { flag, with } => write!(f, "flag '{flag}' can't be used with '{with}'"),
+            ConfigError::NotAFlag(arg) => write!(f, "'{arg}' isn't a flag, and only flags can be set here"),
+            ConfigError::NotADefault(flag) => write!(f, "flag '{flag}' can't be set in the configuration file"),
+            ConfigError::ConfigFile { path, reason } => write!(f, "can't read the configuration file {path}: {reason}"),
+            ConfigError::ConfigLine { path, line, error } => write!(f, "{path}:{line}: {error}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct Config {
+    // From -e, or else the QUERY argument. The lines of the -f files are added by `run`.
+    pub patterns: Vec<String>,
+    pub pattern_files: Vec<String>,
+    pub file_paths: Vec<String>,
+    pub ignore_case: bool,
+    pub regex: bool,
+    pub fuzzy: Option<usize>, // the most typos allowed
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    pub invert_match: bool,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub only_matching: bool,
+    pub count: bool,
+    pub files_with_matches: bool,
+    pub files_without_match: bool,
+    pub max_count: Option<usize>,
+    pub replace: Option<String>,
+    pub in_place: Option<String>, // the backup suffix, empty for no backup
+    pub with_filename: bool,
+    pub json: bool,
+    pub null: bool,
+    pub color: ColorChoice,
+    // Explicit -A/-B values win over -C, whatever their order
+    pub before_context: Option<usize>,
+    pub after_context: Option<usize>,
+    pub context: usize,
+    pub recursive: bool,
+    pub threads: usize, // 0 picks one per CPU
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    pub no_config: bool,
+    pub action: Action,
+}
+
+impl Config {
+    // fn new(args: &[String]) -> Config {
+
+    //     if args.len() < 3 {
+    //         panic!("Not enough arguments");
+    //     }
+
+    //     let query = args[1].clone();
+    //     let file_path = args[2].clone();
+
+    //     Config {query, file_path}
+    // }
+
+    // pub fn build(args: &[String]) -> Result<Config, &'static str> { // -> changed after chapter 13
+    pub fn build(args: impl Iterator<Item = String>, ) -> Result<Config, ConfigError> {
+
+        // Body of Chapter 12:
+        // if args.len() < 3 {
+        //     return Err("not enough arguments")
+        // }
+
+        // let query = args[1].clone();
+        // let file_path = args[2].clone();
+        // let ignore_case = env::var("IGNORE_CASE").is_ok(); // we don't care about the value of the variable, only if it exists
+        // Ok(Config {
+        //     query,
+        //     file_path,
+        //     ignore_case,
+        // })
+
+        // Body of Chapter 13:
+        // args.next();
+        // let query = match args.next() { ... };
+        // let file_path = match args.next() { ... };
+        // let ignore_case = env::var("IGNORE_CASE").is_ok();
+
+        // The environment variable is only the default: -i / --no-ignore-case win over it
+        let args: Vec<String> = args.collect();
+        let env_ignore_case = env::var("IGNORE_CASE").is_ok();
+        // The command line alone says whether there is a configuration file to read;
+        // with one, it is parsed again on top of the file
+        let config = Config::parse(args.iter().cloned(), env_ignore_case, None)?;
+        if config.no_config {
+            return Ok(config);
+        }
+        let defaults = read_defaults()?;
+        Config::parse(args.into_iter(), env_ignore_case, defaults.as_ref().map(|(path, contents)| (path.as_str(), contents.as_str())))
+    }
+
+    // Kept apart from `build` so that tests don't depend on the process environment.
+    // `defaults` is the path and contents of the configuration file.
+    fn parse(mut args: impl Iterator<Item = String>, env_ignore_case: bool, defaults: Option<(&str, &str)>) -> Result<Config, ConfigError> {
+        args.next();
+
+        let mut config = Config { ignore_case: env_ignore_case, ..Config::default() };
+        if let Some((path, contents)) = defaults {
+            config.apply_defaults(path, contents)?;
+        }
+        let mut positional = Vec::new();
+        let mut flags_done = false;
+
+        while let Some(arg) = args.next() {
+            if flags_done || arg == "-" || !arg.starts_with('-') {
+                positional.push(arg);
+            } else if arg == "--" {
+                flags_done = true;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                config.apply_long(long, &mut args)?;
+            } else {
+                config.apply_short_group(&arg[1..], &mut args)?;
+            }
+        }
+
+        // --help and --version don't need a query
+        if config.action != Action::Search {
+            return Ok(config);
+        }
+
+        let mut positional = positional.into_iter();
+        // With -e or -f, every argument left is a file
+        if config.patterns.is_empty() && config.pattern_files.is_empty() {
+            config.patterns.push(positional.next().ok_or(ConfigError::MissingQuery)?);
+        }
+        config.file_paths = positional.collect();
+        if config.file_paths.is_empty() {
+            return Err(ConfigError::MissingFilePath);
+        }
+        if config.in_place.is_some() && config.replace.is_none() {
+            return Err(ConfigError::Requires { flag: "--in-place".to_string(), requires: "--replace".to_string() });
+        }
+        // There is no file to write the standard input back to
+        if config.in_place.is_some() && config.file_paths.iter().any(|path| path == "-") {
+            return Err(ConfigError::Conflicts { flag: "--in-place".to_string(), with: "-".to_string() });
+        }
+        if config.fuzzy.is_some() && config.regex {
+            return Err(ConfigError::Conflicts { flag: "--fuzzy".to_string(), with: "--regex".to_string() });
+        }
+
+        Ok(config)
+    }
+
+    pub fn before(&self) -> usize {
+        self.before_context.unwrap_or(self.context)
+    }
+
+    pub fn after(&self) -> usize {
+        self.after_context.unwrap_or(self.context)
+    }
+
+    pub fn boundary(&self) -> Boundary {
+        if self.line_regexp {
+            Boundary::Line
+        } else if self.word_regexp {
+            Boundary::Word
+        } else {
+            Boundary::None
+        }
+    }
+
+    /// Whether only file names are printed (-l or -L).
+    pub fn lists_files(&self) -> bool {
+        self.files_with_matches || self.files_without_match
+    }
+
+    // One flag per line; a flag whose value isn't attached takes the next line
+    fn apply_defaults(&mut self, path: &str, contents: &str) -> Result<(), ConfigError> {
+        let line = Cell::new(0);
+        let mut args = contents
+            .lines()
+            .enumerate()
+            .map(|(i, arg)| (i + 1, arg.trim()))
+            .filter(|(_, arg)| !arg.is_empty() && !arg.starts_with('#'))
+            .map(|(number, arg)| {
+                line.set(number);
+                arg.to_string()
+            });
+
+        while let Some(arg) = args.next() {
+            let flag_line = line.get();
+            let at = |line: usize| move |error| ConfigError::ConfigLine { path: path.to_string(), line, error: Box::new(error) };
+            // Unknown flags are reported as such, before the ones that can't be defaults
+            match arg.strip_prefix("--") {
+                Some(long) if !long.is_empty() => {
+                    self.apply_long(long, &mut args).map_err(at(line.get()))?;
+                    check_default_long(long).map_err(at(flag_line))?;
+                }
+                _ if arg.len() > 1 && arg.starts_with('-') => {
+                    self.apply_short_group(&arg[1..], &mut args).map_err(at(line.get()))?;
+                    check_default_short_group(&arg[1..]).map_err(at(flag_line))?;
+                }
+                _ => return Err(at(flag_line)(ConfigError::NotAFlag(arg))),
+            }
+        }
+        Ok(())
+    }
+
+    // A group like `-inC2`: a flag taking a value consumes the rest of the group,
+    // or the next argument when it comes last
+    fn apply_short_group(&mut self, group: &str, args: &mut impl Iterator<Item = String>) -> Result<(), ConfigError> {
+        for (i, flag) in group.char_indices() {
+            if !SHORT_FLAGS_WITH_VALUE.contains(flag) {
+                self.apply_short(flag)?;
+                continue;
+            }
+            let rest = &group[i + flag.len_utf8()..];
+            let value = if rest.is_empty() {
+                args.next().ok_or_else(|| ConfigError::MissingValue(format!("-{flag}")))?
+            } else {
+                rest.to_string()
+            };
+            match flag {
+                'e' => self.patterns.push(value),
+                'f' => self.pattern_files.push(value),
+                'A' => self.after_context = Some(parse_number("-A", &value)?),
+                'B' => self.before_context = Some(parse_number("-B", &value)?),
+                'C' => self.context = parse_number("-C", &value)?,
+                'm' => self.max_count = Some(parse_number("-m", &value)?),
+                _ => self.threads = parse_number("-j", &value)?,
+            }
+            break;
+        }
+        Ok(())
+    }
+
+    fn apply_short(&mut self, flag: char) -> Result<(), ConfigError> {
+        match flag {
+            'i' => self.ignore_case = true,
+            'E' => self.regex = true,
+            'w' => self.word_regexp = true,
+            'x' => self.line_regexp = true,
+            'v' => self.invert_match = true,
+            'n' => self.line_number = true,
+            'b' => self.byte_offset = true,
+            'o' => self.only_matching = true,
+            'c' => self.count = true,
+            'l' => self.files_with_matches = true,
+            'L' => self.files_without_match = true,
+            'H' => self.with_filename = true,
+            'Z' => self.null = true,
+            'r' => self.recursive = true,
+            _ => return Err(ConfigError::UnknownFlag(format!("-{flag}"))),
+        }
+        Ok(())
+    }
+
+    // Flags with a value accept both `--flag=value` and `--flag value`
+    fn apply_long(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<(), ConfigError> {
+        let (name, inline_value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| ConfigError::MissingValue(format!("--{name}")))
+        };
+
+        match name {
+            // A bare `--color` means `auto`, so it must not take the next argument
+            "color" | "colour" => {
+                self.color = match &inline_value {
+                    None => ColorChoice::Auto,
+                    Some(when) => ColorChoice::parse(when).ok_or_else(|| ConfigError::InvalidValue {
+                        flag: format!("--{name}"),
+                        value: when.to_string(),
+                    })?,
+                }
+            }
+            "regexp" => self.patterns.push(value()?),
+            "file" => self.pattern_files.push(value()?),
+            // A bare `--in-place` keeps no backup; the suffix has to be attached
+            "in-place" => self.in_place = Some(inline_value.clone().unwrap_or_default()),
+            "replace" => self.replace = Some(value()?),
+            "include" => self.include.push(value()?),
+            "exclude" => self.exclude.push(value()?),
+            "after-context" => self.after_context = Some(parse_number(&format!("--{name}"), &value()?)?),
+            "before-context" => self.before_context = Some(parse_number(&format!("--{name}"), &value()?)?),
+            "context" => self.context = parse_number(&format!("--{name}"), &value()?)?,
+            "fuzzy" => self.fuzzy = Some(parse_number(&format!("--{name}"), &value()?)?),
+            "max-count" => self.max_count = Some(parse_number(&format!("--{name}"), &value()?)?),
+            "threads" => self.threads = parse_number(&format!("--{name}"), &value()?)?,
+            _ if inline_value.is_some() => return Err(ConfigError::UnknownFlag(format!("--{flag}"))),
+            "ignore-case" => self.ignore_case = true,
+            "no-ignore-case" => self.ignore_case = false,
+            "regex" => self.regex = true,
+            "word-regexp" => self.word_regexp = true,
+            "line-regexp" => self.line_regexp = true,
+            "invert-match" => self.invert_match = true,
+            "line-number" => self.line_number = true,
+            "byte-offset" => self.byte_offset = true,
+            "only-matching" => self.only_matching = true,
+            "count" => self.count = true,
+            "files-with-matches" => self.files_with_matches = true,
+            "files-without-match" => self.files_without_match = true,
+            "with-filename" => self.with_filename = true,
+            "json" => self.json = true,
+            "null" => self.null = true,
+            "recursive" => self.recursive = true,
+            "no-config" => self.no_config = true,
+            "hidden" => self.hidden = true,
+            "no-ignore" => self.no_ignore = true,
+            "help" => self.action = Action::Help,
+            "version" => self.action = Action::Version,
+            _ => return Err(ConfigError::UnknownFlag(format!("--{flag}"))),
+        }
+        Ok(())
+    }
+}
+
+// The configuration file: the one MINIGREP_CONFIG names, which must exist, or
+// else ~/.minigreprc if there is one
+fn read_defaults() -> Result<Option<(String, String)>, ConfigError> {
+    let (path, required) = match env::var_os("MINIGREP_CONFIG") {
+        Some(path) if path.is_empty() => return Ok(None),
+        Some(path) => (PathBuf::from(path), true),
+        None => match env::var_os("HOME") {
+            Some(home) => (Path::new(&home).join(".minigreprc"), false),
+            None => return Ok(None),
+        },
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some((path.display().to_string(), contents))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(None),
+        Err(e) => Err(ConfigError::ConfigFile { path: path.display().to_string(), reason: e.to_string() }),
+    }
+}
+
+fn check_default_long(flag: &str) -> Result<(), ConfigError> {
+    let name = flag.split_once('=').map_or(flag, |(name, _)| name);
+    if !DEFAULT_LONG_FLAGS.contains(&name) {
+        return Err(ConfigError::NotADefault(format!("--{name}")));
+    }
+    Ok(())
+}
+
+// Up to the first flag with a value, which takes the rest of the group
+fn check_default_short_group(group: &str) -> Result<(), ConfigError> {
+    for flag in group.chars() {
+        if !DEFAULT_SHORT_FLAGS.contains(flag) {
+            return Err(ConfigError::NotADefault(format!("-{flag}")));
+        }
+        if SHORT_FLAGS_WITH_VALUE.contains(flag) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue { flag: flag.to_string(), value: value.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env_ignore_case: bool) -> Result<Config, ConfigError> {
+        let args = ["minigrep"].iter().chain(args).map(|arg| arg.to_string());
+        Config::parse(args, env_ignore_case, None)
+    }
+
+    fn parse_with_defaults(defaults: &str, args: &[&str]) -> Result<Config, ConfigError> {
+        let args = ["minigrep"].iter().chain(args).map(|arg| arg.to_string());
+        Config::parse(args, true, Some(("minigreprc", defaults)))
+    }
+
+    #[test]
+    fn positional_only() {
+        let config = parse(&["to", "poem.txt"], false).unwrap();
+
+        assert_eq!(vec!["to"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+        assert!(!config.ignore_case && !config.invert_match && !config.recursive);
+        assert_eq!(Action::Search, config.action);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let config = parse(&["-in", "to", "-cv", "-bo", "poem.txt"], false).unwrap();
+
+        assert!(config.ignore_case && config.line_number && config.count && config.invert_match);
+        assert!(config.byte_offset && config.only_matching);
+
+        let config = parse(&["-HZ", "--json", "to", "poem.txt"], false).unwrap();
+        assert!(config.with_filename && config.null && config.json);
+        assert!(!config.regex);
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let config = parse(&["-n", "--", "-v", "-"], false).unwrap();
+
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(vec!["-"], config.file_paths);
+        assert!(config.line_number && !config.invert_match);
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        assert!(parse(&["to", "poem.txt"], true).unwrap().ignore_case);
+        assert!(!parse(&["--no-ignore-case", "to", "poem.txt"], true).unwrap().ignore_case);
+        assert!(parse(&["-i", "to", "poem.txt"], false).unwrap().ignore_case);
+        // the last of the two flags wins
+        assert!(parse(&["--no-ignore-case", "-i", "to", "poem.txt"], false).unwrap().ignore_case);
+    }
+
+    #[test]
+    fn flags_with_values() {
+        let config = parse(&["-r", "--include=*.rs", "--exclude", "target", "fn", "."], false).unwrap();
+
+        assert_eq!(vec!["*.rs"], config.include);
+        assert_eq!(vec!["target"], config.exclude);
+        assert_eq!(vec!["fn"], config.patterns);
+        assert_eq!(vec!["."], config.file_paths);
+        assert_eq!(Err(ConfigError::MissingValue("--include".to_string())), parse(&["--include"], false));
+        assert_eq!(Err(ConfigError::UnknownFlag("--count=2".to_string())), parse(&["--count=2"], false));
+    }
+
+    #[test]
+    fn walk_flags() {
+        let config = parse(&["-r", "--hidden", "--no-ignore", "fn", "."], false).unwrap();
+        assert!(config.hidden && config.no_ignore);
+
+        let config = parse(&["-r", "fn", "."], false).unwrap();
+        assert!(!config.hidden && !config.no_ignore);
+    }
+
+    #[test]
+    fn context_flags() {
+        let config = parse(&["-nC2", "to", "poem.txt"], false).unwrap();
+        assert!(config.line_number);
+        assert_eq!((2, 2), (config.before(), config.after()));
+
+        let config = parse(&["-A", "1", "--context=3", "to", "poem.txt"], false).unwrap();
+        assert_eq!((3, 1), (config.before(), config.after()));
+
+        assert_eq!(
+            Err(ConfigError::InvalidValue { flag: "-B".to_string(), value: "x".to_string() }),
+            parse(&["-Bx", "to", "poem.txt"], false)
+        );
+        assert_eq!(Err(ConfigError::MissingValue("-C".to_string())), parse(&["-C"], false));
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(ColorChoice::Auto, parse(&["to", "poem.txt"], false).unwrap().color);
+        assert_eq!(ColorChoice::Never, parse(&["--color=never", "to", "poem.txt"], false).unwrap().color);
+        let config = parse(&["--color=always", "--colour", "to", "poem.txt"], false).unwrap();
+        assert_eq!((ColorChoice::Auto, "to"), (config.color, config.patterns[0].as_str()));
+        assert_eq!(
+            Err(ConfigError::InvalidValue { flag: "--color".to_string(), value: "sometimes".to_string() }),
+            parse(&["--color=sometimes", "to", "poem.txt"], false)
+        );
+    }
+
+    #[test]
+    fn several_files_and_threads() {
+        let config = parse(&["-j2", "to", "poem.txt", "-", "notes.txt"], false).unwrap();
+        assert_eq!(vec!["poem.txt", "-", "notes.txt"], config.file_paths);
+        assert_eq!(2, config.threads);
+
+        assert_eq!(4, parse(&["--threads=4", "to", "poem.txt"], false).unwrap().threads);
+    }
+
+    #[test]
+    fn several_patterns() {
+        let config = parse(&["-e", "to", "-enobody", "--regexp=-v", "poem.txt"], false).unwrap();
+        assert_eq!(vec!["to", "nobody", "-v"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+
+        // With a pattern file, the first argument is a file too
+        let config = parse(&["-f", "alerts.txt", "app.log", "db.log"], false).unwrap();
+        assert!(config.patterns.is_empty());
+        assert_eq!(vec!["alerts.txt"], config.pattern_files);
+        assert_eq!(vec!["app.log", "db.log"], config.file_paths);
+
+        assert_eq!(Err(ConfigError::MissingFilePath), parse(&["-e", "to"], false));
+        assert_eq!(Err(ConfigError::MissingValue("--file".to_string())), parse(&["--file"], false));
+    }
+
+    #[test]
+    fn file_lists_and_max_count() {
+        let config = parse(&["-lm2", "to", "poem.txt"], false).unwrap();
+        assert!(config.files_with_matches && config.lists_files());
+        assert_eq!(Some(2), config.max_count);
+
+        let config = parse(&["--files-without-match", "--max-count", "0", "to", "poem.txt"], false).unwrap();
+        assert!(config.files_without_match);
+        assert_eq!(Some(0), config.max_count);
+
+        // -Z only changes what comes after the file names
+        assert!(!parse(&["-Z", "to", "poem.txt"], false).unwrap().lists_files());
+        assert!(!parse(&["-c", "to", "poem.txt"], false).unwrap().lists_files());
+    }
+
+    #[test]
+    fn word_and_line_regexp() {
+        assert_eq!(Boundary::None, parse(&["to", "poem.txt"], false).unwrap().boundary());
+        assert_eq!(Boundary::Word, parse(&["-iw", "to", "poem.txt"], false).unwrap().boundary());
+        assert_eq!(Boundary::Line, parse(&["--line-regexp", "to", "poem.txt"], false).unwrap().boundary());
+        assert_eq!(Boundary::Line, parse(&["-w", "-x", "to", "poem.txt"], false).unwrap().boundary());
+    }
+
+    #[test]
+    fn replace_and_in_place() {
+        let config = parse(&["--replace", "you", "--in-place=.bak", "to", "poem.txt"], false).unwrap();
+        assert_eq!((Some("you"), Some(".bak")), (config.replace.as_deref(), config.in_place.as_deref()));
+
+        let config = parse(&["--replace=", "--in-place", "to", "poem.txt"], false).unwrap();
+        assert_eq!((Some(""), Some("")), (config.replace.as_deref(), config.in_place.as_deref()));
+        assert_eq!(vec!["to"], config.patterns);
+
+        assert_eq!(
+            Err(ConfigError::Requires { flag: "--in-place".to_string(), requires: "--replace".to_string() }),
+            parse(&["--in-place", "to", "poem.txt"], false)
+        );
+        assert_eq!(
+            Err(ConfigError::Conflicts { flag: "--in-place".to_string(), with: "-".to_string() }),
+            parse(&["--replace=you", "--in-place", "to", "poem.txt", "-"], false)
+        );
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(Some(2), parse(&["--fuzzy=2", "config", "app.toml"], false).unwrap().fuzzy);
+        assert_eq!(Some(1), parse(&["--fuzzy", "1", "config", "app.toml"], false).unwrap().fuzzy);
+        assert_eq!(
+            Err(ConfigError::Conflicts { flag: "--fuzzy".to_string(), with: "--regex".to_string() }),
+            parse(&["-E", "--fuzzy=1", "config", "app.toml"], false)
+        );
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert_eq!(Action::Help, parse(&["--help"], false).unwrap().action);
+        assert_eq!(Action::Version, parse(&["-i", "--version"], false).unwrap().action);
+    }
+
+    #[test]
+    fn structured_errors() {
+        assert_eq!(Err(ConfigError::MissingQuery), parse(&["-i"], false));
+        assert_eq!(Err(ConfigError::MissingFilePath), parse(&["to"], false));
+        assert_eq!(Err(ConfigError::UnknownFlag("-y".to_string())), parse(&["-iy", "to", "poem.txt"], false));
+        assert_eq!(Err(ConfigError::UnknownFlag("--frobnicate".to_string())), parse(&["--frobnicate"], false));
+    }
+
+    #[test]
+    fn configuration_file() {
+        let defaults = "# always show some context\n-C2\n--color=always\n\n--no-ignore-case\n--exclude\ntarget\n";
+
+        let config = parse_with_defaults(defaults, &["to", "poem.txt"]).unwrap();
+        assert_eq!((2, ColorChoice::Always, false), (config.context, config.color, config.ignore_case));
+        assert_eq!(vec!["target"], config.exclude);
+        // The command line wins, or adds to lists
+        let config = parse_with_defaults(defaults, &["-C0", "--color=never", "-i", "--exclude=*.lock", "to", "poem.txt"]).unwrap();
+        assert_eq!((0, ColorChoice::Never, true), (config.context, config.color, config.ignore_case));
+        assert_eq!(vec!["target", "*.lock"], config.exclude);
+        assert!(parse_with_defaults(defaults, &["--no-config", "to", "poem.txt"]).unwrap().no_config);
+        // Only as a flag: here it is the pattern
+        let config = parse(&["-e", "--no-config", "poem.txt"], false).unwrap();
+        assert_eq!((vec!["--no-config".to_string()], false), (config.patterns, config.no_config));
+    }
+
+    #[test]
+    fn configuration_file_errors() {
+        let line = |line, error| Err(ConfigError::ConfigLine { path: "minigreprc".to_string(), line, error: Box::new(error) });
+
+        assert_eq!(line(3, ConfigError::UnknownFlag("--colour-me".to_string())), parse_with_defaults("-n\n\n--colour-me\n", &["to", "poem.txt"]));
+        assert_eq!(line(2, ConfigError::NotAFlag("poem.txt".to_string())), parse_with_defaults("# files\npoem.txt\n", &["to"]));
+        assert_eq!(line(1, ConfigError::MissingValue("-C".to_string())), parse_with_defaults("-C", &["to", "poem.txt"]));
+        // Patterns, modes and actions only make sense on the command line
+        assert_eq!(line(2, ConfigError::NotADefault("-e".to_string())), parse_with_defaults("-n\n-e\nfrog\n", &["How", "poem.txt"]));
+        assert_eq!(line(1, ConfigError::NotADefault("-f".to_string())), parse_with_defaults("-nfalerts.txt", &["How", "poem.txt"]));
+        assert_eq!(line(1, ConfigError::NotADefault("--replace".to_string())), parse_with_defaults("--replace=x", &["How", "poem.txt"]));
+        for flag in ["--regexp=frog", "--file=alerts.txt", "--in-place", "--help", "--version", "--no-config"] {
+            assert!(matches!(parse_with_defaults(flag, &["How", "poem.txt"]), Err(ConfigError::ConfigLine { line: 1, .. })), "{flag}");
+        }
+        assert_eq!(
+            "minigreprc:4: invalid value 'lots' for flag '-A'",
+            parse_with_defaults("\n\n-A\nlots\n", &["to", "poem.txt"]).unwrap_err().to_string()
+        );
+    }
+}
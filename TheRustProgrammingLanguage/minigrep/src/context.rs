@@ -0,0 +1,94 @@
+// Context lines around matches (-A/-B/-C).
+//
+// Every selected line opens a window of `before` lines above and `after` lines
+// below it; windows that overlap or touch are merged into a single group, and
+// groups are printed with a `--` line between them.
+
+pub const SEPARATOR: &str = "--";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContextLine<'a> {
+    pub number: usize, // 1-based, like `-n` prints it
+    pub line: &'a str,
+    pub is_match: bool,
+}
+
+/// Groups `lines` around the ones flagged in `selected` (both slices have the same length).
+pub fn groups<'a>(lines: &[&'a str], selected: &[bool], before: usize, after: usize) -> Vec<Vec<ContextLine<'a>>> {
+    let mut groups: Vec<Vec<ContextLine>> = Vec::new();
+    // One past the last line already placed in a group
+    let mut end = 0;
+
+    for (i, _) in selected.iter().enumerate().filter(|(_, &is_selected)| is_selected) {
+        let start = i.saturating_sub(before).max(end);
+        let stop = (i + after + 1).min(lines.len());
+
+        // Starting right where the previous window ended means the windows touch
+        if groups.is_empty() || start > end {
+            groups.push(Vec::new());
+        }
+        let group = groups.last_mut().unwrap();
+        // Empty when the previous window already reached further down
+        for (i, (&line, &is_match)) in lines.iter().zip(selected).enumerate().take(stop).skip(start) {
+            group.push(ContextLine { number: i + 1, line, is_match });
+        }
+        end = end.max(stop);
+    }
+
+    groups
+}
+
+/// Flags the lines of `contents` returned by one of the line-based search functions.
+pub fn selected_lines(contents: &str, results: &[&str]) -> Vec<bool> {
+    // The search functions return sub-slices of `contents`, in order, so a single
+    // pass over the lines is enough to find them again
+    let mut results = results.iter().peekable();
+    contents
+        .lines()
+        .map(|line| results.next_if(|result| result.as_ptr() == line.as_ptr()).is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(groups: &[Vec<ContextLine>]) -> Vec<Vec<usize>> {
+        groups.iter().map(|group| group.iter().map(|line| line.number).collect()).collect()
+    }
+
+    const LINES: [&str; 10] = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+
+    fn select(numbers: &[usize]) -> Vec<bool> {
+        (1..=LINES.len()).map(|number| numbers.contains(&number)).collect()
+    }
+
+    #[test]
+    fn before_and_after() {
+        let groups = groups(&LINES, &select(&[5]), 2, 1);
+
+        assert_eq!(vec![vec![3, 4, 5, 6]], numbers(&groups));
+        assert_eq!(vec![false, false, true, false], groups[0].iter().map(|line| line.is_match).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn separate_groups() {
+        let groups = groups(&LINES, &select(&[2, 8]), 1, 1);
+
+        assert_eq!(vec![vec![1, 2, 3], vec![7, 8, 9]], numbers(&groups));
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_windows_merge() {
+        assert_eq!(vec![vec![2, 3, 4, 5, 6]], numbers(&groups(&LINES, &select(&[3, 5]), 1, 1)));
+        // windows 1..=3 and 4..=6 touch, so no separator between them
+        assert_eq!(vec![vec![1, 2, 3, 4, 5, 6]], numbers(&groups(&LINES, &select(&[2, 5]), 1, 1)));
+        // a long `after` window swallowing the next match
+        assert_eq!(vec![vec![1, 2, 3, 4, 5, 6]], numbers(&groups(&LINES, &select(&[1, 2]), 0, 4)));
+    }
+
+    #[test]
+    fn clamped_to_the_edges() {
+        assert_eq!(vec![vec![1, 2], vec![7, 8, 9, 10]], numbers(&groups(&LINES, &select(&[1, 10]), 3, 1)));
+    }
+}
@@ -0,0 +1,289 @@
+// Text that isn't UTF-8: the encoding of an input is guessed from its first
+// bytes, and UTF-16 is converted to UTF-8 as it is read.
+//
+// A byte order mark settles the question. Without one, UTF-16 shows itself by
+// the NUL bytes that ASCII characters leave on one side of every pair, as long
+// as what it decodes to looks like text: binary data, like an array of small
+// integers, has that pattern too. Anything else that isn't valid UTF-8 is
+// taken for Latin-1, whose bytes are the first 256 code points. Only the start
+// is looked at here: the stream switches to Latin-1 at the first line that
+// isn't UTF-8, wherever it is.
+
+use std::char::REPLACEMENT_CHARACTER;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::mem;
+
+use crate::walk;
+
+// Number of leading bytes inspected when guessing the encoding
+const SNIFF_LEN: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    /// The encoding `start` seems to be in, and the length of its byte order mark.
+    pub fn detect(start: &[u8]) -> (Encoding, usize) {
+        match start {
+            [0xef, 0xbb, 0xbf, ..] => return (Encoding::Utf8, 3),
+            [0xff, 0xfe, ..] => return (Encoding::Utf16Le, 2),
+            [0xfe, 0xff, ..] => return (Encoding::Utf16Be, 2),
+            _ => {}
+        }
+        let sample = &start[..start.len().min(SNIFF_LEN)];
+        let units = sample.len() / 2;
+        let nul_at = |parity| sample.iter().skip(parity).step_by(2).filter(|&&byte| byte == 0).count();
+        let (even, odd) = (nul_at(0), nul_at(1));
+        if units >= 2 && even == 0 && odd * 2 > units && is_utf16_text(sample, false) {
+            return (Encoding::Utf16Le, 0);
+        }
+        if units >= 2 && odd == 0 && even * 2 > units && is_utf16_text(sample, true) {
+            return (Encoding::Utf16Be, 0);
+        }
+        match std::str::from_utf8(sample) {
+            // A character cut in two at the end of the sample is no evidence
+            Err(e) if e.error_len().is_some() => (Encoding::Latin1, 0),
+            _ => (Encoding::Utf8, 0),
+        }
+    }
+}
+
+/// Whether `start` is binary data, to be skipped by `-r`: it has NUL bytes, and
+/// they aren't those of UTF-16 text, with or without a byte order mark.
+pub fn is_binary(start: &[u8]) -> bool {
+    if !walk::is_binary(start) {
+        return false;
+    }
+    match Encoding::detect(start) {
+        (encoding @ (Encoding::Utf16Le | Encoding::Utf16Be), bom_len) => {
+            let sample = &start[bom_len..start.len().min(SNIFF_LEN)];
+            // An odd byte at the end is no evidence either way
+            !is_utf16_text(&sample[..sample.len() & !1], encoding == Encoding::Utf16Be)
+        }
+        _ => true,
+    }
+}
+
+// No unpaired surrogates, and no control characters but the whitespace ones.
+// A surrogate pair cut in two at the end of the sample doesn't count.
+fn is_utf16_text(sample: &[u8], big_endian: bool) -> bool {
+    let units = sample.chunks_exact(2).map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if big_endian {
+            u16::from_be_bytes(pair)
+        } else {
+            u16::from_le_bytes(pair)
+        }
+    });
+    let mut chars = char::decode_utf16(units).peekable();
+    while let Some(c) = chars.next() {
+        let is_text = match c {
+            Ok(c) => !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'),
+            Err(e) => chars.peek().is_none() && (0xd800..0xdc00).contains(&e.unpaired_surrogate()),
+        };
+        if !is_text {
+            return false;
+        }
+    }
+    true
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        })
+    }
+}
+
+/// `reader` in UTF-8 (but for Latin-1, which is left as it is), without its
+/// byte order mark, and the encoding it was found to be in.
+pub fn decode<'a>(mut reader: impl BufRead + 'a) -> io::Result<(Encoding, Box<dyn BufRead + 'a>)> {
+    let (encoding, bom_len) = Encoding::detect(reader.fill_buf()?);
+    reader.consume(bom_len);
+    Ok(match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let big_endian = encoding == Encoding::Utf16Be;
+            (encoding, Box::new(io::BufReader::new(Utf16Decoder::new(reader, big_endian))))
+        }
+        _ => (encoding, Box::new(reader)),
+    })
+}
+
+/// A line of Latin-1, where every byte is the code point of the same value.
+pub fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+/// Reads UTF-16 as UTF-8. Unpaired surrogates and an odd byte at the end
+/// become U+FFFD REPLACEMENT CHARACTER.
+pub struct Utf16Decoder<R> {
+    input: R,
+    big_endian: bool,
+    carry: Vec<u8>,   // an odd byte or a high surrogate, waiting for the rest
+    decoded: Vec<u8>, // UTF-8 not handed out yet, from `pos` on
+    pos: usize,
+}
+
+impl<R: BufRead> Utf16Decoder<R> {
+    pub fn new(input: R, big_endian: bool) -> Utf16Decoder<R> {
+        Utf16Decoder { input, big_endian, carry: Vec::new(), decoded: Vec::new(), pos: 0 }
+    }
+
+    fn unit(&self, pair: &[u8]) -> u16 {
+        let pair = [pair[0], pair[1]];
+        if self.big_endian {
+            u16::from_be_bytes(pair)
+        } else {
+            u16::from_le_bytes(pair)
+        }
+    }
+
+    // Decodes the next chunk of input; false at the end of it
+    fn refill(&mut self) -> io::Result<bool> {
+        let chunk = self.input.fill_buf()?;
+        if chunk.is_empty() {
+            if self.carry.is_empty() {
+                return Ok(false);
+            }
+            self.carry.clear();
+            self.decoded = REPLACEMENT_CHARACTER.to_string().into_bytes();
+            self.pos = 0;
+            return Ok(true);
+        }
+        let mut bytes = mem::take(&mut self.carry);
+        bytes.extend_from_slice(chunk);
+        let read = chunk.len();
+        self.input.consume(read);
+
+        // A surrogate pair may be cut in two by the end of the chunk
+        let mut end = bytes.len() & !1;
+        if end >= 2 && (0xd800..0xdc00).contains(&self.unit(&bytes[end - 2..end])) {
+            end -= 2;
+        }
+        self.carry = bytes.split_off(end);
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| self.unit(pair)).collect();
+        let text: String = char::decode_utf16(units).map(|c| c.unwrap_or(REPLACEMENT_CHARACTER)).collect();
+        self.decoded = text.into_bytes();
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for Utf16Decoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if !self.refill()? {
+                return Ok(0);
+            }
+        }
+        let n = out.len().min(self.decoded.len() - self.pos);
+        out[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect()
+    }
+
+    fn decoded(bytes: &[u8]) -> (Encoding, String) {
+        let (encoding, mut reader) = decode(bytes).unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        (encoding, text)
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!((Encoding::Utf8, 3), Encoding::detect(b"\xef\xbb\xbfhello"));
+        assert_eq!((Encoding::Utf16Le, 2), Encoding::detect(b"\xff\xfeh\0"));
+        assert_eq!((Encoding::Utf16Be, 2), Encoding::detect(b"\xfe\xff\0h"));
+        assert_eq!((Encoding::Utf16Le, 0), Encoding::detect(&utf16("hello", false)));
+        assert_eq!((Encoding::Utf16Be, 0), Encoding::detect(&utf16("hello", true)));
+        assert_eq!((Encoding::Latin1, 0), Encoding::detect(b"caf\xe9 cr\xe8me"));
+        assert_eq!((Encoding::Utf8, 0), Encoding::detect("café".as_bytes()));
+        assert_eq!((Encoding::Utf8, 0), Encoding::detect(&"café".as_bytes()[..4])); // cut in the middle of 'é'
+        assert_eq!((Encoding::Utf8, 0), Encoding::detect(b""));
+    }
+
+    #[test]
+    fn binary_with_utf16_nuls() {
+        // Little-endian 16-bit integers: a NUL in every odd byte, like ASCII in UTF-16LE
+        let integers: Vec<u8> = (1..=40u16).flat_map(u16::to_le_bytes).collect();
+        assert_eq!((Encoding::Utf8, 0), Encoding::detect(&integers));
+        let mut unpaired = utf16("text", true);
+        unpaired.extend([0xdc, 0x00]); // a low surrogate on its own
+        unpaired.extend(utf16("more", true));
+        assert_eq!((Encoding::Latin1, 0), Encoding::detect(&unpaired));
+        // Text cut in the middle of a pair is still text
+        let cut = utf16("ok 𝄞", false);
+        assert_eq!((Encoding::Utf16Le, 0), Encoding::detect(&cut[..cut.len() - 2]));
+        assert_eq!((Encoding::Utf16Le, 0), Encoding::detect(&utf16("tab\there\r\n", false)));
+    }
+
+    #[test]
+    fn binary_data() {
+        assert!(!is_binary(b"plain text"));
+        assert!(!is_binary(&utf16("text", false)));
+        let mut with_bom = vec![0xfe, 0xff];
+        with_bom.extend(utf16("text", true));
+        assert!(!is_binary(&with_bom));
+        // A byte order mark doesn't make what follows it text: counting bytes
+        // run into unpaired surrogates
+        let counting: Vec<u8> = [0xff, 0xfe].into_iter().chain(0..=255).collect();
+        assert!(is_binary(&counting));
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\x00"));
+    }
+
+    #[test]
+    fn utf16_to_utf8() {
+        let text = "Log 𝄞 café\r\nline two\r\n";
+        let mut with_bom = vec![0xff, 0xfe];
+        with_bom.extend(utf16(text, false));
+
+        assert_eq!((Encoding::Utf16Le, text.to_string()), decoded(&with_bom));
+        assert_eq!((Encoding::Utf16Be, text.to_string()), decoded(&utf16(text, true)));
+        assert_eq!((Encoding::Utf8, "plain".to_string()), decoded(b"\xef\xbb\xbfplain"));
+
+        // A tiny buffer, so that surrogate pairs get cut in two between chunks
+        let pairs = "𝄞𝄞 a𝄞b 😀";
+        let bytes = utf16(pairs, true);
+        let mut text = String::new();
+        Utf16Decoder::new(io::BufReader::with_capacity(3, &bytes[..]), true).read_to_string(&mut text).unwrap();
+        assert_eq!(pairs, text);
+    }
+
+    #[test]
+    fn damaged_utf16() {
+        // With a byte order mark: damage like this would keep a guess from taking it for UTF-16
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(utf16("ok", false));
+        bytes.extend([0x3d, 0xd8]); // a high surrogate with nothing after it
+        bytes.extend(utf16("?", false));
+        bytes.push(b'x'); // and half a unit
+
+        assert_eq!((Encoding::Utf16Le, "ok\u{fffd}?\u{fffd}".to_string()), decoded(&bytes));
+    }
+
+    #[test]
+    fn latin1_lines() {
+        assert_eq!("café crème", latin1(b"caf\xe9 cr\xe8me"));
+    }
+}
@@ -0,0 +1,267 @@
+// Approximate matching for `--fuzzy`: occurrences of the query within an edit
+// distance of k, counting inserted, deleted and substituted characters.
+//
+// Myers' bit-parallel algorithm keeps a whole column of the edit distance table
+// in two machine words, so every character of a line costs a handful of bit
+// operations for queries of up to 64 characters. Longer queries fill the table
+// column by column instead.
+
+use std::collections::HashMap;
+
+use crate::casefold;
+use crate::matches::Span;
+
+// Queries up to this many characters fit in one `u64` per column
+const WORD_BITS: usize = 64;
+
+pub struct Fuzzy {
+    pattern: Vec<char>,
+    reversed: Vec<char>,
+    max_distance: usize,
+    ignore_case: bool,
+}
+
+impl Fuzzy {
+    pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Fuzzy {
+        let pattern: Vec<char> = if ignore_case { casefold::fold_str(query) } else { query.chars().collect() };
+        let reversed = pattern.iter().rev().copied().collect();
+        Fuzzy { pattern, reversed, max_distance, ignore_case }
+    }
+
+    /// The closest occurrence in `line` and its distance, if it is within
+    /// `max_distance`. Among equally close ones, the one ending first wins, made
+    /// as long as it gets at no extra cost: `frg` finds all of "frog", not "fr".
+    pub fn find(&self, line: &str) -> Option<(Span, usize)> {
+        let chars: Vec<(usize, char)> = line.char_indices().map(|(i, c)| (i, self.fold(c))).collect();
+        let byte = |index: usize| chars.get(index).map_or(line.len(), |&(offset, _)| offset);
+
+        // Before reading anything, the query is all deletions away
+        let (mut distance, mut end) = (self.pattern.len(), 0);
+        distances(&self.pattern, chars.iter().map(|&(_, c)| c), false, |n, d| {
+            if d < distance {
+                (distance, end) = (d, n);
+            } else if d == distance && n == end + 1 && d < self.pattern.len() {
+                end = n;
+            }
+        });
+        if distance > self.max_distance {
+            return None;
+        }
+
+        // Read backwards from the end to find where the occurrence starts
+        let mut start = None;
+        if distance == self.pattern.len() {
+            start = Some(end);
+        }
+        distances(&self.reversed, chars[..end].iter().rev().map(|&(_, c)| c), true, |n, d| {
+            if d == distance && start.is_none() {
+                start = Some(end - n);
+            }
+        });
+        Some(((byte(start.unwrap_or(0)), byte(end)), distance))
+    }
+
+    /// Like `find`, for the occurrences `accept` agrees to. When the closest one
+    /// is turned down, every occurrence within `max_distance` is a candidate, the
+    /// closest first and then from left to right: `-w` passes over `frog` inside
+    /// "leapfrog" for `frg` further on.
+    pub fn find_where(&self, line: &str, accept: impl Fn(Span) -> bool) -> Option<(Span, usize)> {
+        if let Some(found) = self.find(line).filter(|&(span, _)| accept(span)) {
+            return Some(found);
+        }
+        let chars: Vec<(usize, char)> = line.char_indices().map(|(i, c)| (i, self.fold(c))).collect();
+        let byte = |index: usize| chars.get(index).map_or(line.len(), |&(offset, _)| offset);
+        let (m, k) = (self.pattern.len(), self.max_distance);
+
+        // Where occurrences end, then where each of them may start: an occurrence
+        // is never more than k characters longer than the query
+        let mut ends = Vec::new();
+        distances(&self.pattern, chars.iter().map(|&(_, c)| c), false, |n, d| {
+            if d <= k {
+                ends.push(n);
+            }
+        });
+        let mut candidates = Vec::new();
+        if m <= k {
+            candidates.extend((0..=chars.len()).map(|n| (m, n, n)));
+        }
+        for end in ends {
+            distances(&self.reversed, chars[..end].iter().rev().map(|&(_, c)| c).take(m + k), true, |n, d| {
+                if d <= k {
+                    candidates.push((d, end - n, end));
+                }
+            });
+        }
+        candidates.sort_unstable();
+        candidates.into_iter().map(|(d, start, end)| ((byte(start), byte(end)), d)).find(|&(span, _)| accept(span))
+    }
+
+    fn fold(&self, c: char) -> char {
+        if self.ignore_case {
+            casefold::fold(c)
+        } else {
+            c
+        }
+    }
+}
+
+/// The edit distance between `query` and `text`.
+pub fn distance(query: &str, text: &str, ignore_case: bool) -> usize {
+    let fold = |c| if ignore_case { casefold::fold(c) } else { c };
+    let pattern: Vec<char> = query.chars().map(fold).collect();
+    let mut last = pattern.len();
+    distances(&pattern, text.chars().map(fold), true, |_, d| last = d);
+    last
+}
+
+// Calls `found(n, d)` after each of the first n characters of `text`, where d
+// is the edit distance between `pattern` and the closest substring of `text`
+// ending there or, when `anchored`, the first n characters themselves.
+fn distances(pattern: &[char], text: impl Iterator<Item = char>, anchored: bool, mut found: impl FnMut(usize, usize)) {
+    let m = pattern.len();
+    if m == 0 {
+        for (j, _) in text.enumerate() {
+            found(j + 1, if anchored { j + 1 } else { 0 });
+        }
+        return;
+    }
+    if m > WORD_BITS {
+        return table_distances(pattern, text, anchored, found);
+    }
+
+    // Bit i of `peq[c]` is set where pattern[i] == c
+    let mut peq: HashMap<char, u64> = HashMap::new();
+    for (i, &c) in pattern.iter().enumerate() {
+        *peq.entry(c).or_default() |= 1 << i;
+    }
+    let last_row = 1u64 << (m - 1);
+    // The vertical differences of the current column, +1 (`pv`) or -1 (`mv`)
+    let (mut pv, mut mv) = (!0u64, 0u64);
+    let mut score = m;
+    for (j, c) in text.enumerate() {
+        let eq = peq.get(&c).copied().unwrap_or(0);
+        let xv = eq | mv;
+        let xh = ((eq & pv).wrapping_add(pv) ^ pv) | eq;
+        let mut ph = mv | !(xh | pv);
+        let mut mh = pv & xh;
+        if ph & last_row != 0 {
+            score += 1;
+        } else if mh & last_row != 0 {
+            score -= 1;
+        }
+        // The top row is all zeros when the occurrence may start anywhere,
+        // and counts the skipped characters otherwise
+        ph <<= 1;
+        mh <<= 1;
+        if anchored {
+            ph |= 1;
+        }
+        pv = mh | !(xv | ph);
+        mv = ph & xv;
+        found(j + 1, score);
+    }
+}
+
+// The same, one cell at a time
+fn table_distances(pattern: &[char], text: impl Iterator<Item = char>, anchored: bool, mut found: impl FnMut(usize, usize)) {
+    let mut column: Vec<usize> = (0..=pattern.len()).collect();
+    for (j, c) in text.enumerate() {
+        let mut diagonal = column[0];
+        column[0] = if anchored { j + 1 } else { 0 };
+        for (i, &expected) in pattern.iter().enumerate() {
+            let cost = usize::from(expected != c);
+            let value = (diagonal + cost).min(column[i + 1] + 1).min(column[i] + 1);
+            diagonal = column[i + 1];
+            column[i + 1] = value;
+        }
+        found(j + 1, column[pattern.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matches::Boundary;
+
+    fn find(query: &str, max_distance: usize, line: &str) -> Option<(Span, usize)> {
+        Fuzzy::new(query, max_distance, false).find(line)
+    }
+
+    #[test]
+    fn typos() {
+        assert_eq!(Some(((4, 10), 0)), find("config", 1, "let config = 1;"));
+        assert_eq!(Some(((4, 9), 1)), find("config", 1, "let confg = 1;")); // deletion
+        assert_eq!(Some(((4, 11), 1)), find("config", 1, "let connfig = 1;")); // insertion
+        assert_eq!(Some(((4, 10), 1)), find("config", 1, "let cinfig = 1;")); // substitution
+        assert_eq!(None, find("config", 1, "let cnfg = 1;"));
+        assert_eq!(Some(((4, 8), 2)), find("config", 2, "let cnfg = 1;"));
+    }
+
+    #[test]
+    fn closest_occurrence_wins() {
+        assert_eq!(Some(((11, 17), 0)), find("needle", 2, "neddle and needle"));
+        assert_eq!(Some(((0, 6), 1)), find("needle", 2, "neddle and nedle"));
+    }
+
+    #[test]
+    fn longest_at_the_same_cost() {
+        assert_eq!(Some(((19, 23), 1)), find("frg", 1, "How public, like a frog"));
+        assert_eq!(Some(((2, 4), 1)), find("frg", 1, "a fr"));
+    }
+
+    #[test]
+    fn candidates_until_one_is_accepted() {
+        let fuzzy = Fuzzy::new("frog", 1, false);
+        let word = |line: &str| fuzzy.find_where(line, |span| Boundary::Word.accepts(line, span));
+
+        assert_eq!(Some(((0, 4), 0)), word("frog (afrog)"));
+        assert_eq!(Some(((0, 5), 1)), word("frogs"));
+        // The closest occurrence is inside a word, the one after it isn't
+        assert_eq!(Some(((9, 12), 1)), word("leapfrog frg"));
+        assert_eq!(None, fuzzy.find_where("afrog", |_| false));
+    }
+
+    #[test]
+    fn unicode_and_case() {
+        let fuzzy = Fuzzy::new("ΣΟΦΟΣ", 1, true);
+
+        assert_eq!(Some(((4, 12), 1)), fuzzy.find("ὁ σοφς")); // offsets in bytes
+        assert_eq!(None, Fuzzy::new("ΣΟΦΟΣ", 1, false).find("ὁ σοφς"));
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(Some(((0, 0), 0)), find("", 0, "anything"));
+        // A query no longer than k matches every line
+        assert_eq!(Some(((0, 0), 2)), find("ab", 2, "xyz"));
+        assert_eq!(None, find("abc", 1, ""));
+    }
+
+    #[test]
+    fn long_queries_use_the_table() {
+        let query = "a".repeat(70) + "b";
+        let line = format!("xx{}cxx", "a".repeat(70));
+
+        assert_eq!(Some(((2, 73), 1)), find(&query, 3, &line));
+        assert_eq!(1, distance(&query, &line[2..73], false));
+    }
+
+    #[test]
+    fn distances_match_the_table() {
+        let words = ["kitten", "sitting", "", "flaw", "lawn", "Straße", "strasse"];
+        for a in words {
+            for b in words {
+                let pattern: Vec<char> = a.chars().collect();
+                let (mut bits, mut cells) = (Vec::new(), Vec::new());
+                distances(&pattern, b.chars(), false, |n, d| bits.push((n, d)));
+                table_distances(&pattern, b.chars(), false, |n, d| cells.push((n, d)));
+                if !pattern.is_empty() {
+                    assert_eq!(cells, bits, "{a} in {b}");
+                }
+            }
+        }
+        assert_eq!(3, distance("kitten", "sitting", false));
+        assert_eq!(2, distance("flaw", "lawn", false));
+        assert_eq!(2, distance("straße", "STRASSE", true));
+    }
+}
@@ -0,0 +1,178 @@
+// Shell-style wildcard patterns, used to filter the files visited by `-r`.
+//
+//   `?`       any character but `/`
+//   `*`       any run of characters without `/`
+//   `**`      any run of characters, `/` included
+//   `**/`     zero or more whole directories
+//   `[a-z]`   a character class, negated with `[!...]` or `[^...]`
+//   `\x`      the character `x` itself
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any,
+    Star,
+    DoubleStar,
+    DirStar,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let token = match chars[i] {
+                '?' => Token::Any,
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    if chars.get(i + 1) == Some(&'/') {
+                        i += 1;
+                        Token::DirStar
+                    } else {
+                        Token::DoubleStar
+                    }
+                }
+                '*' => Token::Star,
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Literal(chars[i])
+                }
+                '[' => match parse_class(&chars, i) {
+                    Some((token, end)) => {
+                        i = end;
+                        token
+                    }
+                    None => Token::Literal('['), // an unclosed bracket is taken literally
+                },
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        Glob { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut memo = vec![None; (self.tokens.len() + 1) * (chars.len() + 1)];
+        self.matches_from(&chars, 0, 0, &mut memo)
+    }
+
+    fn matches_from(&self, chars: &[char], t: usize, c: usize, memo: &mut [Option<bool>]) -> bool {
+        let key = t * (chars.len() + 1) + c;
+        if let Some(result) = memo[key] {
+            return result;
+        }
+
+        let result = match self.tokens.get(t) {
+            None => c == chars.len(),
+            Some(Token::Star) => {
+                // Try every length of the run, stopping at the first `/`
+                let mut end = c;
+                loop {
+                    if self.matches_from(chars, t + 1, end, memo) {
+                        break true;
+                    }
+                    if end == chars.len() || chars[end] == '/' {
+                        break false;
+                    }
+                    end += 1;
+                }
+            }
+            Some(Token::DoubleStar) => (c..=chars.len()).any(|end| self.matches_from(chars, t + 1, end, memo)),
+            Some(Token::DirStar) => {
+                self.matches_from(chars, t + 1, c, memo)
+                    || (c..chars.len())
+                        .filter(|&i| chars[i] == '/')
+                        .any(|i| self.matches_from(chars, t + 1, i + 1, memo))
+            }
+            Some(token) => {
+                c < chars.len()
+                    && match token {
+                        Token::Literal(expected) => chars[c] == *expected,
+                        Token::Class { ranges, negated } => {
+                            chars[c] != '/'
+                                && ranges.iter().any(|&(lo, hi)| lo <= chars[c] && chars[c] <= hi) != *negated
+                        }
+                        _ => chars[c] != '/',
+                    }
+                    && self.matches_from(chars, t + 1, c + 1, memo)
+            }
+        };
+
+        memo[key] = Some(result);
+        result
+    }
+}
+
+// Parses the class starting at `chars[start] == '['`, returning it with the index of its `]`.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let mut lo = *chars.get(i)?;
+        if lo == ']' && !first {
+            return Some((Token::Class { ranges, negated }, i));
+        }
+        if lo == '\\' {
+            i += 1;
+            lo = *chars.get(i)?;
+        }
+        first = false;
+        let mut hi = lo;
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&c| c != ']') {
+            hi = chars[i + 2];
+            i += 2;
+        }
+        ranges.push((lo, hi));
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(Glob::new("*.rs").matches("lib.rs"));
+        assert!(!Glob::new("*.rs").matches("lib.rs.bak"));
+        assert!(!Glob::new("*.rs").matches("src/lib.rs"));
+        assert!(Glob::new("file?.txt").matches("file1.txt"));
+        assert!(!Glob::new("file?.txt").matches("file.txt"));
+        assert!(Glob::new(r"\*.txt").matches("*.txt"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(Glob::new("log[0-9].txt").matches("log7.txt"));
+        assert!(!Glob::new("log[!0-9].txt").matches("log7.txt"));
+        assert!(Glob::new("[]a]").matches("]"));
+        assert!(Glob::new("[ab").matches("[ab"));
+    }
+
+    #[test]
+    fn double_stars() {
+        assert!(Glob::new("src/**").matches("src/a/b.rs"));
+        assert!(Glob::new("**/*.rs").matches("main.rs"));
+        assert!(Glob::new("**/*.rs").matches("src/bin/main.rs"));
+        assert!(Glob::new("a/**/b").matches("a/b"));
+        assert!(Glob::new("a/**/b").matches("a/x/y/b"));
+        assert!(!Glob::new("a/**/b").matches("a/xb"));
+    }
+}
@@ -0,0 +1,201 @@
+// `.gitignore` and `.ignore` rules, which keep `-r` out of build output and
+// other files nobody wants to search.
+//
+// The rules follow gitignore(5): blank lines and `#` comments are skipped, a
+// leading `!` re-includes what an earlier rule ignored, a trailing `/` only
+// matches directories, and a pattern with a `/` anywhere but at its end is
+// anchored to the directory of its file. Other patterns match a name at any
+// depth below it. The last matching rule wins, and rules in a subdirectory win
+// over those of its parents; `.ignore` wins over `.gitignore` in the same place.
+//
+// Inside a git repository, the ignore files of the directories above the one
+// being searched count too, up to the top of the repository, and so does its
+// `.git/info/exclude`, below all of them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::glob::Glob;
+
+// Read in this order, so that the rules of the later ones win
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        // Trailing spaces don't count unless escaped with a backslash
+        let mut line = line.trim_end_matches(['\r', '\n']);
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])).unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let glob = Glob::new(line.strip_prefix('/').unwrap_or(line));
+        Some(Rule { glob, negated, dir_only, anchored })
+    }
+
+    // `relative` is the path below the directory of the rule's file
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.glob.matches(relative)
+        } else {
+            self.glob.matches(relative.rsplit('/').next().unwrap_or(relative))
+        }
+    }
+}
+
+/// The ignore rules in force in a directory: those of its own ignore files,
+/// then those of the directories above it.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    dir: PathBuf,
+    // Where `dir` is below the directory of the rules' file; empty unless that
+    // file is above the root of the walk, whose paths all start with `dir`
+    above: PathBuf,
+    rules: Vec<Rule>,
+    parent: Option<Rc<Ignore>>,
+}
+
+impl Ignore {
+    /// The rules for `dir`, whose parent directory has those of `parent`. When
+    /// `dir` has no ignore files, that is `parent` itself.
+    pub fn child(parent: &Rc<Ignore>, dir: &Path) -> Rc<Ignore> {
+        Ignore::from_lines(dir, &read(dir, &IGNORE_FILES)).on_top_of(parent)
+    }
+
+    /// The rules in force in `root`, where a walk starts, before its own ignore
+    /// files: those of the directories above it, up to the top of its git
+    /// repository, and `.git/info/exclude`. Outside of a repository there are none.
+    pub fn above(root: &Path) -> Rc<Ignore> {
+        let mut ignore = Rc::default();
+        let Ok(absolute) = fs::canonicalize(root) else {
+            return ignore;
+        };
+        let Some(top) = absolute.ancestors().find(|dir| dir.join(".git").exists()) else {
+            return ignore;
+        };
+        // Each level keeps the paths of the walk, and knows where `root` is below its directory
+        let level = |dir: &Path, contents: &str| Ignore {
+            above: absolute.strip_prefix(dir).unwrap_or(&absolute).to_path_buf(),
+            ..Ignore::from_lines(root, contents)
+        };
+        ignore = level(top, &read(&top.join(".git/info"), &["exclude"])).on_top_of(&ignore);
+        let dirs: Vec<&Path> = absolute.ancestors().skip(1).take_while(|dir| dir.starts_with(top)).collect();
+        for dir in dirs.into_iter().rev() {
+            ignore = level(dir, &read(dir, &IGNORE_FILES)).on_top_of(&ignore);
+        }
+        ignore
+    }
+
+    fn from_lines(dir: &Path, contents: &str) -> Ignore {
+        Ignore {
+            dir: dir.to_path_buf(),
+            above: PathBuf::new(),
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+            parent: None,
+        }
+    }
+
+    fn on_top_of(self, parent: &Rc<Ignore>) -> Rc<Ignore> {
+        if self.rules.is_empty() {
+            return Rc::clone(parent);
+        }
+        Rc::new(Ignore { parent: Some(Rc::clone(parent)), ..self })
+    }
+
+    /// Whether the rules leave out `path`, a file or directory below `dir`.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignore = Some(self);
+        while let Some(current) = ignore {
+            if let Ok(relative) = path.strip_prefix(&current.dir) {
+                let relative = current.above.join(relative).to_string_lossy().replace('\\', "/");
+                if let Some(rule) = current.rules.iter().rev().find(|rule| rule.matches(&relative, is_dir)) {
+                    return !rule.negated;
+                }
+            }
+            ignore = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+// The ignore files `names` in `dir`, one after another; an unreadable one is as good as none
+fn read(dir: &Path, names: &[&str]) -> String {
+    let contents: Vec<String> = names.iter().filter_map(|name| fs::read_to_string(dir.join(name)).ok()).collect();
+    contents.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> bool {
+        Ignore::from_lines(Path::new("repo"), rules).is_ignored(&Path::new("repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn names_at_any_depth() {
+        let rules = "# build output\n\n*.log\ntarget\n";
+
+        assert!(ignored(rules, "debug.log", false));
+        assert!(ignored(rules, "a/b/debug.log", false));
+        assert!(ignored(rules, "crates/x/target", true));
+        assert!(!ignored(rules, "src/main.rs", false));
+        assert!(!ignored(rules, "# build output", false));
+    }
+
+    #[test]
+    fn anchored_and_directory_patterns() {
+        let rules = "/build\ndocs/*.html\nout/\n";
+
+        assert!(ignored(rules, "build", true));
+        assert!(!ignored(rules, "src/build", true));
+        assert!(ignored(rules, "docs/index.html", false));
+        assert!(!ignored(rules, "docs/api/index.html", false));
+        assert!(ignored(rules, "src/out", true));
+        assert!(!ignored(rules, "src/out", false)); // a file, not a directory
+    }
+
+    #[test]
+    fn negations() {
+        let rules = "*.txt\n!keep.txt\n";
+
+        assert!(ignored(rules, "notes.txt", false));
+        assert!(!ignored(rules, "keep.txt", false));
+        // The last matching rule wins
+        assert!(ignored("!keep.txt\n*.txt\n", "keep.txt", false));
+        assert!(ignored(r"\!important", "!important", false));
+    }
+
+    #[test]
+    fn double_stars_and_spaces() {
+        assert!(ignored("**/cache/**\n", "a/cache/b/c.bin", false));
+        assert!(ignored("logs/**/*.gz\n", "logs/2024/01/app.gz", false));
+        assert!(ignored("notes.txt   \n", "notes.txt", false));
+        assert!(!ignored(r"notes.txt\ ", "notes.txt", false));
+    }
+}
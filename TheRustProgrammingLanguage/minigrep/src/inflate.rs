@@ -0,0 +1,529 @@
+// Decompression of gzip (RFC 1952) and zlib (RFC 1950) input, so that rotated
+// logs can be searched without unpacking them first.
+//
+// Both formats wrap a deflate stream (RFC 1951), decoded here as it is read:
+// besides the lines being searched, only the last 32 KiB of output are kept,
+// since that is as far back as deflate can refer. Checksums are verified at the
+// end of every stream, and concatenated gzip members are read one after another.
+
+use std::io::{self, BufRead, Read};
+
+// How far back a length/distance pair can copy from
+const WINDOW: usize = 1 << 15;
+const MAX_CODE_BITS: usize = 15;
+// Output is handed out in chunks of about this size
+const CHUNK: usize = 1 << 14;
+// How much output `Format::inflates` decodes before taking the input for compressed
+const TRIAL: usize = 1 << 16;
+
+// Base values and extra bits of the length symbols 257..=285 and of the distance symbols
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order of the code length code lengths in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Zlib,
+}
+
+impl Format {
+    /// Recognises compressed input from its first bytes.
+    pub fn detect(start: &[u8]) -> Option<Format> {
+        match start {
+            [0x1f, 0x8b, ..] => Some(Format::Gzip),
+            // A 32 KiB window, no preset dictionary, and a header checksum that
+            // holds. Level 2 to 5 streams start with "x^", which plain text does
+            // too, so they aren't recognised.
+            [0x78, flags, ..] if (0x7800 | *flags as u16).is_multiple_of(31) && flags & 0x20 == 0 && flags >> 6 != 1 => {
+                Some(Format::Zlib)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `start`, the first bytes of the input, decompress without an
+    /// error. Text can still look like a header ("xÚ" in Latin-1 is one), but
+    /// not like the deflate stream after it.
+    pub fn inflates(self, start: &[u8]) -> bool {
+        // A sample that runs out in the middle of the stream is fine
+        match io::copy(&mut Decoder::new(start, self).take(TRIAL as u64), &mut io::sink()) {
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        }
+    }
+}
+
+/// Reads the decompressed contents of `input`.
+pub struct Decoder<R> {
+    input: Bits<R>,
+    format: Format,
+    state: State,
+    last_block: bool,
+    window: Vec<u8>,
+    written: usize, // by the current stream, the window position is `written % WINDOW`
+    checksum: u32,
+    adler: (u32, u32),
+    output: Vec<u8>,
+    read: usize, // how much of `output` was handed out
+}
+
+enum State {
+    Header,
+    Block,
+    Stored(usize),
+    Codes(Box<(Huffman, Huffman)>), // literals and lengths, distances
+    Trailer,
+    Done,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(input: R, format: Format) -> Decoder<R> {
+        Decoder {
+            input: Bits { inner: input, buf: 0, count: 0 },
+            format,
+            state: State::Header,
+            last_block: false,
+            window: vec![0; WINDOW],
+            written: 0,
+            checksum: 0,
+            adler: (1, 0),
+            output: Vec::with_capacity(CHUNK + 258),
+            read: 0,
+        }
+    }
+
+    // Moves on by one step: a header, a block header, or a chunk of output
+    fn step(&mut self) -> io::Result<()> {
+        let start = self.output.len();
+        self.state = match std::mem::replace(&mut self.state, State::Done) {
+            State::Header => self.header()?,
+            State::Block if self.last_block => State::Trailer,
+            State::Block => self.block()?,
+            State::Stored(left) => {
+                let len = left.min(CHUNK);
+                for _ in 0..len {
+                    let byte = self.input.bits(8)? as u8;
+                    self.push(byte);
+                }
+                if left == len { State::Block } else { State::Stored(left - len) }
+            }
+            State::Codes(codes) => {
+                if self.codes(&codes.0, &codes.1)? { State::Block } else { State::Codes(codes) }
+            }
+            State::Trailer => self.trailer()?,
+            State::Done => State::Done,
+        };
+
+        let output = &self.output[start..];
+        self.checksum = crc32(self.checksum, output);
+        let (mut a, mut b) = self.adler;
+        for &byte in output {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        self.adler = (a, b);
+        Ok(())
+    }
+
+    fn header(&mut self) -> io::Result<State> {
+        let bits = &mut self.input;
+        match self.format {
+            Format::Gzip => {
+                if (bits.bits(8)?, bits.bits(8)?) != (0x1f, 0x8b) {
+                    return Err(invalid("not in gzip format"));
+                }
+                if bits.bits(8)? != 8 {
+                    return Err(invalid("unknown gzip compression method"));
+                }
+                let flags = bits.bits(8)?;
+                bits.skip(6)?; // modification time, extra flags, operating system
+                if flags & 0x04 != 0 {
+                    let len = bits.bits(16)?;
+                    bits.skip(len as usize)?;
+                }
+                // The original file name, then a comment, both NUL-terminated
+                for flag in [0x08, 0x10] {
+                    if flags & flag != 0 {
+                        while bits.bits(8)? != 0 {}
+                    }
+                }
+                if flags & 0x02 != 0 {
+                    bits.skip(2)?; // header CRC
+                }
+            }
+            Format::Zlib => {
+                let (method, flags) = (bits.bits(8)?, bits.bits(8)?);
+                if method & 0x0f != 8 || (method << 8 | flags) % 31 != 0 {
+                    return Err(invalid("not in zlib format"));
+                }
+                if flags & 0x20 != 0 {
+                    return Err(invalid("zlib preset dictionaries are not supported"));
+                }
+            }
+        }
+        self.last_block = false;
+        self.written = 0;
+        self.checksum = 0;
+        self.adler = (1, 0);
+        Ok(State::Block)
+    }
+
+    fn block(&mut self) -> io::Result<State> {
+        self.last_block = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => {
+                self.input.align();
+                let len = self.input.bits(16)?;
+                if len != !self.input.bits(16)? & 0xffff {
+                    return Err(invalid("corrupt stored block length"));
+                }
+                Ok(State::Stored(len as usize))
+            }
+            1 => Ok(State::Codes(Box::new(fixed_codes()))),
+            2 => Ok(State::Codes(Box::new(self.dynamic_codes()?))),
+            _ => Err(invalid("invalid block type")),
+        }
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let bits = &mut self.input;
+        let literals = bits.bits(5)? as usize + 257;
+        let distances = bits.bits(5)? as usize + 1;
+        let code_lengths = bits.bits(4)? as usize + 4;
+
+        let mut lengths = [0; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = bits.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            let (length, repeat) = match code_length_code.decode(bits)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => match lengths.last() {
+                    Some(&previous) => (previous, 3 + bits.bits(2)?),
+                    None => return Err(invalid("repeated code length without a previous one")),
+                },
+                17 => (0, 3 + bits.bits(3)?),
+                _ => (0, 11 + bits.bits(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(length, repeat as usize));
+        }
+        if lengths.len() > literals + distances {
+            return Err(invalid("too many code lengths"));
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("no end of block code"));
+        }
+        Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+    }
+
+    // Decodes symbols until the end of the block (returns true) or a full chunk
+    fn codes(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<bool> {
+        while self.output.len() < CHUNK {
+            let symbol = literals.decode(&mut self.input)? as usize;
+            if symbol < 256 {
+                self.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(true);
+            }
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid("invalid length symbol"));
+            }
+            let len = LENGTH_BASE[symbol] as usize + self.input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+            let symbol = distances.decode(&mut self.input)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(invalid("invalid distance symbol"));
+            }
+            let distance = DISTANCE_BASE[symbol] as usize + self.input.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+            if distance > self.written.min(WINDOW) {
+                return Err(invalid("distance too far back"));
+            }
+            // One byte at a time: the copy may overlap what it produces
+            for _ in 0..len {
+                let byte = self.window[(self.written - distance) % WINDOW];
+                self.push(byte);
+            }
+        }
+        Ok(false)
+    }
+
+    fn trailer(&mut self) -> io::Result<State> {
+        let bits = &mut self.input;
+        bits.align();
+        match self.format {
+            Format::Gzip => {
+                let checksum = bits.bits(16)? | bits.bits(16)? << 16;
+                let size = bits.bits(16)? | bits.bits(16)? << 16;
+                if checksum != self.checksum || size != self.written as u32 {
+                    return Err(invalid("gzip checksum mismatch"));
+                }
+                // Another member may follow, as with `cat a.gz b.gz`; anything else is ignored, like gzip does
+                if Format::detect(bits.inner.fill_buf()?) == Some(Format::Gzip) {
+                    return Ok(State::Header);
+                }
+            }
+            Format::Zlib => {
+                // Big-endian, unlike everything else
+                let mut checksum = 0;
+                for _ in 0..4 {
+                    checksum = checksum << 8 | bits.bits(8)?;
+                }
+                if checksum != self.adler.1 << 16 | self.adler.0 {
+                    return Err(invalid("zlib checksum mismatch"));
+                }
+            }
+        }
+        Ok(State::Done)
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.window[self.written % WINDOW] = byte;
+        self.written += 1;
+        self.output.push(byte);
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.output.len() {
+            if matches!(self.state, State::Done) {
+                return Ok(0);
+            }
+            self.output.clear();
+            self.read = 0;
+            self.step()?;
+        }
+        let len = buf.len().min(self.output.len() - self.read);
+        buf[..len].copy_from_slice(&self.output[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+// Deflate packs bits starting from the least significant one of each byte
+struct Bits<R> {
+    inner: R,
+    buf: u64,
+    count: u32,
+}
+
+impl<R: BufRead> Bits<R> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = match self.inner.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "compressed input is truncated")),
+            };
+            self.inner.consume(1);
+            self.buf |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+        let value = (self.buf & ((1 << n) - 1)) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Drops the bits left in the current byte
+    fn align(&mut self) {
+        self.buf >>= self.count % 8;
+        self.count -= self.count % 8;
+    }
+
+    fn skip(&mut self, bytes: usize) -> io::Result<()> {
+        for _ in 0..bytes {
+            self.bits(8)?;
+        }
+        Ok(())
+    }
+}
+
+// A canonical Huffman code, decoded one bit at a time: the codes of each length
+// are consecutive numbers, given to the symbols in order
+struct Huffman {
+    counts: [u16; MAX_CODE_BITS + 1], // number of codes of each length
+    symbols: Vec<u16>,                // ordered by code
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; MAX_CODE_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        // More codes of some length than there is room for can't be decoded
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_BITS + 2];
+        for len in 1..=MAX_CODE_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut Bits<R>) -> io::Result<u16> {
+        // `first` is the first code of the current length, `index` its symbol
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    // Both are complete codes, which can't fail
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid compressed data: {message}"))
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POEM: &str = include_str!("../poem.txt");
+
+    fn decompress(bytes: &[u8]) -> io::Result<String> {
+        let format = Format::detect(bytes).expect("compressed input");
+        let mut text = String::new();
+        Decoder::new(bytes, format).read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(Some(Format::Gzip), Format::detect(include_bytes!("../poem.txt.gz")));
+        assert_eq!(Some(Format::Zlib), Format::detect(include_bytes!("../poem.txt.zz")));
+        assert_eq!(None, Format::detect(POEM.as_bytes()));
+        assert_eq!(None, Format::detect(b"x^2 + y^2"));
+        assert_eq!(None, Format::detect(b""));
+        // Right first byte, wrong header checksum
+        assert_eq!(None, Format::detect(b"x9 = 0"));
+    }
+
+    #[test]
+    fn text_that_looks_compressed() {
+        let latin1 = b"x\xdanico camino: caf\xe9\n";
+        assert_eq!(Some(Format::Zlib), Format::detect(latin1));
+        assert!(!Format::Zlib.inflates(latin1));
+
+        let gzip = include_bytes!("../poem.txt.gz");
+        assert!(Format::Gzip.inflates(gzip));
+        assert!(Format::Gzip.inflates(&gzip[..40])); // only the start of it
+    }
+
+    #[test]
+    fn fixtures() {
+        // Both use dynamic Huffman codes; the gzip one also has the original file name
+        assert_eq!(POEM, decompress(include_bytes!("../poem.txt.gz")).unwrap());
+        assert_eq!(POEM, decompress(include_bytes!("../poem.txt.zz")).unwrap());
+    }
+
+    #[test]
+    fn stored_and_fixed_blocks() {
+        let stored = [
+            0x78, 0x01, 0x01, 0x18, 0x00, 0xe7, 0xff, 0x53, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x2c, 0x20, 0x6e, 0x6f, 0x74,
+            0x20, 0x63, 0x6f, 0x6d, 0x70, 0x72, 0x65, 0x73, 0x73, 0x65, 0x64, 0x2e, 0x0a, 0x6f, 0x16, 0x08, 0x9c,
+        ];
+        assert_eq!("Stored, not compressed.\n", decompress(&stored).unwrap());
+
+        // With back-references overlapping what they copy
+        let fixed = [0x78, 0xda, 0x4b, 0x4c, 0x52, 0x48, 0x44, 0x20, 0x2e, 0x00, 0x24, 0xb6, 0x04, 0x5a];
+        assert_eq!("ab ab ab ab ab\n", decompress(&fixed).unwrap());
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let gzip = include_bytes!("../poem.txt.gz");
+
+        assert_eq!(POEM.repeat(2), decompress(&[&gzip[..], &gzip[..]].concat()).unwrap());
+    }
+
+    #[test]
+    fn damaged_input() {
+        let gzip = include_bytes!("../poem.txt.gz");
+
+        let truncated = decompress(&gzip[..gzip.len() - 20]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, truncated.kind());
+
+        let mut corrupt = gzip.to_vec();
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 1; // in the size
+        assert_eq!(io::ErrorKind::InvalidData, decompress(&corrupt).unwrap_err().kind());
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xcbf4_3926, crc32(0, b"123456789"));
+        assert_eq!(0, crc32(0, b""));
+    }
+}
@@ -0,0 +1,108 @@
+// A tiny JSON writer for `--json`: one object per line, built by hand since
+// minigrep has no dependencies.
+//
+//   {"path":"poem.txt","line_number":2,"byte_offset":25,"text":"Are you nobody, too?","spans":[{"start":8,"end":14}]}
+
+use std::io::{self, Write};
+
+use crate::matches::Match;
+
+/// Writes `value` as a JSON string, quotes included.
+pub fn write_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    let mut last = 0;
+    for (i, c) in value.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{8}' => "\\b",
+            '\u{c}' => "\\f",
+            c if (c as u32) < 0x20 => "",
+            _ => continue,
+        };
+        write!(out, "{}", &value[last..i])?;
+        if escaped.is_empty() {
+            write!(out, "\\u{:04x}", c as u32)?;
+        } else {
+            write!(out, "{escaped}")?;
+        }
+        last = i + c.len_utf8();
+    }
+    write!(out, "{}\"", &value[last..])
+}
+
+fn write_path(out: &mut impl Write, path: Option<&str>) -> io::Result<()> {
+    write!(out, "\"path\":")?;
+    match path {
+        Some(path) => write_string(out, path),
+        None => write!(out, "null"), // standard input
+    }
+}
+
+/// One selected line; `spans` are byte offsets into the line as it was read,
+/// which is `text` unless the line was Latin-1. `distance` is only there with `--fuzzy`.
+pub fn write_match(out: &mut impl Write, path: Option<&str>, found: &Match, distance: Option<usize>) -> io::Result<()> {
+    write!(out, "{{")?;
+    write_path(out, path)?;
+    write!(out, ",\"line_number\":{},\"byte_offset\":{},", found.line_number, found.byte_offset)?;
+    if let Some(distance) = distance {
+        write!(out, "\"distance\":{distance},")?;
+    }
+    write!(out, "\"text\":")?;
+    write_string(out, found.line)?;
+    write!(out, ",\"spans\":[")?;
+    for (i, (start, end)) in found.spans.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{{\"start\":{start},\"end\":{end}}}")?;
+    }
+    writeln!(out, "]}}")
+}
+
+/// The summary printed by `--json --count`.
+pub fn write_count(out: &mut impl Write, path: Option<&str>, count: usize) -> io::Result<()> {
+    write!(out, "{{")?;
+    write_path(out, path)?;
+    writeln!(out, ",\"count\":{count}}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> String {
+        let mut out = Vec::new();
+        write_string(&mut out, value).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(r#""plain""#, string("plain"));
+        assert_eq!(r#""say \"hi\" \\ bye""#, string(r#"say "hi" \ bye"#));
+        assert_eq!(r#""tab\there\r\n""#, string("tab\there\r\n"));
+        assert_eq!(r#""\u001b[0m \u0000""#, string("\x1b[0m \0"));
+        assert_eq!(r#""Здравствуйте""#, string("Здравствуйте"));
+    }
+
+    #[test]
+    fn match_object() {
+        let found = Match { line_number: 2, byte_offset: 25, line: "Are you \"nobody\"", spans: vec![(8, 10), (12, 14)] };
+        let mut out = Vec::new();
+        write_match(&mut out, Some("dir/poem.txt"), &found, None).unwrap();
+        write_count(&mut out, None, 3).unwrap();
+        write_match(&mut out, None, &Match { spans: vec![(0, 3)], ..found }, Some(1)).unwrap();
+
+        assert_eq!(
+            "{\"path\":\"dir/poem.txt\",\"line_number\":2,\"byte_offset\":25,\"text\":\"Are you \\\"nobody\\\"\",\
+             \"spans\":[{\"start\":8,\"end\":10},{\"start\":12,\"end\":14}]}\n\
+             {\"path\":null,\"count\":3}\n\
+             {\"path\":null,\"line_number\":2,\"byte_offset\":25,\"distance\":1,\"text\":\"Are you \\\"nobody\\\"\",\"spans\":[{\"start\":0,\"end\":3}]}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -1,83 +1,412 @@
-use std::fs; 
-use std::error::Error; 
+use std::collections::BTreeMap; 
 use std::env; 
+use std::error::Error; 
+use std::fs::{self, File}; 
+use std::io::{self, BufRead, BufReader, IsTerminal, Write}; 
+use std::mem; 
+use std::panic::{self, AssertUnwindSafe}; 
+use std::path::{Path, PathBuf}; 
+use std::sync::{mpsc, Arc, Condvar, Mutex}; 
+use std::thread; 
+
+mod aho_corasick; 
+mod casefold; 
+mod color; 
+mod config; 
+mod context; 
+mod encoding; 
+mod fuzzy; 
+mod glob; 
+mod ignore; 
+mod inflate; 
+mod json; 
+mod matches; 
+mod pool; 
+mod regex; 
+mod replace; 
+mod searcher; 
+mod stream; 
+mod walk; 
+
+pub use crate::aho_corasick::AhoCorasick; 
+pub use crate::color::ColorChoice; 
+pub use crate::config::{Action, Config, ConfigError}; 
+pub use crate::context::ContextLine; 
+pub use crate::fuzzy::Fuzzy; 
+pub use crate::glob::Glob; 
+pub use crate::matches::{Boundary, Match, Span}; 
+pub use crate::regex::{Regex, RegexError}; 
+pub use crate::searcher::{CaseInsensitive, Literal, Matcher, Searcher, Sink}; 
+pub use crate::stream::search_reader; 
+pub use crate::walk::Walk; 
 
-pub struct Config {
-    pub query : String, 
-    pub file_path: String, 
-    pub ignore_case: bool, 
+use crate::encoding::Encoding; 
+use crate::pool::ThreadPool; 
+
+/// How a search went, for the exit code of `minigrep`. Like grep's: 0 when
+/// something was found, 1 when nothing was, and 2 when a file couldn't be
+/// searched, even if others had matches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Success, 
+    NoMatch, 
+    Error, 
 }
 
-impl Config {
-    // fn new(args: &[String]) -> Config {
+impl Status {
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Success => 0, 
+            Status::NoMatch => 1, 
+            Status::Error => 2, 
+        }
+    }
+}
 
-    //     if args.len() < 3 {
-    //         panic!("Not enough arguments");
-    //     }
+// pub fn run(mut config: Config) -> Result<(), Box<dyn Error>> { // -> the exit code needs a `Status`
+pub fn run(mut config: Config) -> Result<Status, Box<dyn Error>> {
+    match config.action {
+        Action::Help => {
+            println!("{}", config::USAGE); 
+            return Ok(Status::Success); 
+        }
+        Action::Version => {
+            println!("{}", config::VERSION); 
+            return Ok(Status::Success); 
+        }
+        Action::Search => {}
+    }
 
-    //     let query = args[1].clone(); 
-    //     let file_path = args[2].clone(); 
+    // From here on, the patterns include the lines of the -f files, for the
+    // finder and for the distances printed by --fuzzy
+    config.patterns = patterns(&config)?; 
+    let find = finder(&config)?; 
 
-    //     Config {query, file_path}
-    // }
+    // Lines are written as soon as they are found, so `minigrep` can sit in a pipeline
+    let stdout = io::stdout(); 
+    config.color = config.color.resolve(stdout.is_terminal(), env::var_os("NO_COLOR").is_some()); 
+    // Colors would end up inside the JSON strings
+    if config.json {
+        config.color = ColorChoice::Never; 
+    }
+    // With more than one file, every line says where it comes from
+    config.with_filename |= config.recursive || config.file_paths.len() > 1; 
+    let mut out = stdout.lock(); 
+    match search_files(Arc::new(config), &mut out, find) {
+        // The reader went away (e.g. `minigrep ... | head`): nothing left to do
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(Status::Success), 
+        result => Ok(result?), 
+    }
+}
 
-    // pub fn build(args: &[String]) -> Result<Config, &'static str> { // -> changed after chapter 13 
-    pub fn build(mut args: impl Iterator<Item = String>, ) -> Result<Config, &'static str> {
-
-        // Body of Chapter 12: 
-        // if args.len() < 3 {
-        //     return Err("not enough arguments")
-        // }
-
-        // let query = args[1].clone(); 
-        // let file_path = args[2].clone(); 
-        // let ignore_case = env::var("IGNORE_CASE").is_ok(); // we don't care about the value of the variable, only if it exists 
-        // Ok(Config {
-        //     query, 
-        //     file_path, 
-        //     ignore_case, 
-        // })
-
-        // Body of Chapter 13: 
-        args.next(); 
-
-        let query = match args.next() {
-            Some(arg) => arg, 
-            None => return Err("Didn't get a query string"), 
+// Compiled once, not once per file, and shared by the worker threads
+fn finder(config: &Config) -> Result<Arc<Finder>, Box<dyn Error>> {
+    let patterns = config.patterns.clone(); 
+    let ignore_case = config.ignore_case; 
+    let boundary = config.boundary(); 
+    if let Some(max_distance) = config.fuzzy {
+        let matchers: Vec<Fuzzy> = patterns.iter().map(|pattern| Fuzzy::new(pattern, max_distance, ignore_case)).collect(); 
+        // The closest occurrence of any of the patterns, among those on word or line edges
+        return Ok(Arc::new(move |line: &str| {
+            matchers 
+                .iter()
+                .filter_map(|matcher| matcher.find_where(line, |span| boundary.accepts(line, span)))
+                .min_by_key(|&((start, _), distance)| (distance, start))
+                .map(|(span, _)| span)
+                .into_iter()
+                .collect()
+        })); 
+    }
+    if config.regex && !patterns.is_empty() {
+        let mut pattern = alternatives(&patterns, ignore_case)?; 
+        // Anchored, the regex itself looks for an alternative that fills the line
+        if boundary == Boundary::Line {
+            pattern = format!("^(?:{pattern})$"); 
+        }
+        // For whole words too, the regex checks the edges of a match itself, so
+        // that a longer alternative gets its turn when a shorter one is cut off
+        let regex = match boundary {
+            Boundary::Word => Regex::build_words(&pattern, ignore_case)?, 
+            _ => Regex::build(&pattern, ignore_case)?, 
         }; 
+        return Ok(Arc::new(move |line: &str| matches::regex_spans(&regex, line))); 
+    }
+    if let [query] = patterns.as_slice() {
+        let query = query.clone(); 
+        let query_folded = casefold::fold_str(&query); 
+        return Ok(Arc::new(move |line: &str| {
+            // Try running with: IGNORE_CASE=1 cargo run -- to poem.txt
+            // to unset: unset IGNORE_CASE
+            match (ignore_case, boundary) {
+                (true, Boundary::None) => matches::case_insensitive_spans(&query_folded, line), 
+                (false, Boundary::None) => matches::literal_spans(&query, line), 
+                (true, _) => matches::bounded_spans(line, boundary, |line, pos| matches::case_insensitive_at(&query_folded, line, pos)), 
+                (false, _) => matches::bounded_spans(line, boundary, |line, pos| matches::literal_at(&query, line, pos)), 
+            }
+        })); 
+    }
+    // Hundreds of patterns still take a single pass over each line
+    let automaton = AhoCorasick::new(&patterns, ignore_case); 
+    Ok(Arc::new(move |line: &str| automaton.find_spans_where(line, |span| boundary.accepts(line, span))))
+}
+
+// The -e patterns (or QUERY) followed by the lines of the -f files
+fn patterns(config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut patterns = config.patterns.clone(); 
+    for file in &config.pattern_files {
+        let contents = fs::read_to_string(file).map_err(|e| format!("{file}: {e}"))?; 
+        patterns.extend(contents.lines().map(String::from)); 
+    }
+    Ok(patterns)
+}
+
+// Several regular expressions become the alternatives of a single one. Each is
+// checked on its own first, so that errors point into the pattern as written.
+fn alternatives(patterns: &[String], ignore_case: bool) -> Result<String, RegexError> {
+    if let [pattern] = patterns {
+        return Ok(pattern.clone()); 
+    }
+    for pattern in patterns {
+        Regex::build(pattern, ignore_case)?; 
+    }
+    Ok(patterns.iter().map(|pattern| format!("(?:{pattern})")).collect::<Vec<_>>().join("|"))
+}
+
+// Finds the spans of the patterns in a line
+type Finder = dyn Fn(&str) -> Vec<Span> + Send + Sync; 
+
+// What a worker hands back for one file: some of its output as it comes, then
+// the rest of it with how the search ended
+enum FileResult {
+    Output(usize, Vec<u8>), 
+    Done(usize, PathBuf, Vec<u8>, io::Result<usize>), 
+}
+
+// How much output a worker holds on to before it waits for its file's turn
+const JOB_BUFFER_LEN: usize = 64 * 1024; 
+
+fn search_files(config: Arc<Config>, out: &mut impl Write, find: Arc<Finder>) -> io::Result<Status> {
+    // With -L, a file counts as found when it is listed, i.e. when nothing was selected in it
+    let found = |selected: usize| (selected > 0) != config.files_without_match; 
+
+    // A single file is streamed straight to `out`, without any buffering
+    if !config.recursive && config.file_paths.len() == 1 {
+        let selected = search_path(&config, out, Path::new(&config.file_paths[0]), false, &*find)?; 
+        return Ok(if found(selected) { Status::Success } else { Status::NoMatch }); 
+    }
+
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()), 
+        n => n, 
+    }; 
+    // Dropped in reverse: waiting workers are let go before the pool waits for them
+    let pool = ThreadPool::new(threads); 
+    let (sender, receiver) = mpsc::channel::<FileResult>(); 
+    let turn = Arc::new(Turn::default()); 
+    let _release = Release(Arc::clone(&turn)); 
+
+    // Files finish in any order but are written in the order they were queued,
+    // the one whose turn it is as its output comes in
+    let mut pending = BTreeMap::<usize, (Vec<u8>, Option<_>)>::new(); 
+    let mut next = 0; 
+    let mut status = Status::NoMatch; 
+    let mut receive = |result: FileResult| -> io::Result<()> {
+        match result {
+            FileResult::Output(index, output) if index == next => return out.write_all(&output), 
+            FileResult::Output(index, output) => pending.entry(index).or_default().0.extend(output), 
+            FileResult::Done(index, path, output, result) => {
+                let file = pending.entry(index).or_default(); 
+                file.0.extend(output); 
+                file.1 = Some((path, result)); 
+            }
+        }
+        while let Some((output, done)) = pending.get_mut(&next) {
+            out.write_all(&mem::take(output))?; 
+            let Some((path, result)) = done.take() else { break }; 
+            pending.remove(&next); 
+            match result {
+                // An error anywhere wins over the matches elsewhere
+                Ok(selected) if found(selected) && status == Status::NoMatch => status = Status::Success, 
+                Ok(_) => {}
+                Err(e) => {
+                    status = Status::Error; 
+                    if path.as_os_str().is_empty() {
+                        eprintln!("minigrep: {e}"); 
+                    } else {
+                        eprintln!("minigrep: {}: {e}", path.display()); 
+                    }
+                }
+            }
+            next += 1; 
+            turn.set(next); 
+        }
+        Ok(())
+    }; 
 
-        let file_path = match args.next() {
-            Some(arg) => arg, 
-            None => return Err("Didn't get a file path"),
+    for (index, input) in inputs(&config).enumerate() {
+        let (path, found_by_walk) = match input {
+            Ok(input) => input, 
+            Err(e) => {
+                // Reported in its place among the results
+                let _ = sender.send(FileResult::Done(index, PathBuf::new(), Vec::new(), Err(e))); 
+                continue; 
+            }
         }; 
+        let config = Arc::clone(&config); 
+        let find = Arc::clone(&find); 
+        let mut output = JobOutput { index, buffer: Vec::new(), sender: sender.clone(), turn: Arc::clone(&turn) }; 
+        pool.execute(move || {
+            // A search that panics is an error for its file, and the others go on
+            let result = panic::catch_unwind(AssertUnwindSafe(|| search_path(&config, &mut output, &path, found_by_walk, &*find)))
+                .unwrap_or_else(|_| Err(io::Error::other("the search panicked"))); 
+            // The receiver only goes away when writing the results failed
+            let _ = output.sender.send(FileResult::Done(index, path, output.buffer, result)); 
+        }); 
+        // What is ready already goes out while the walk goes on
+        for result in receiver.try_iter() {
+            receive(result)?; 
+        }
+    }
+    drop(sender); 
+    for result in receiver {
+        receive(result)?; 
+    }
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+    Ok(status)
+}
 
-        Ok(Config{
-            query, 
-            file_path, 
-            ignore_case,
-        })
+// The index of the file whose output is being written
+#[derive(Default)]
+struct Turn {
+    next: Mutex<usize>, 
+    changed: Condvar, 
+}
+
+impl Turn {
+    fn wait_for(&self, index: usize) {
+        let mut next = self.next.lock().unwrap(); 
+        while *next < index {
+            next = self.changed.wait(next).unwrap(); 
+        }
+    }
+
+    fn set(&self, next: usize) {
+        *self.next.lock().unwrap() = next; 
+        self.changed.notify_all(); 
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?; //.expect("Unable to read file.");
-    // println!("With text:\n{contents}"); 
+// Once the results stop being written, every worker's turn has come: its
+// output has nowhere to go, so it fails instead of waiting forever
+struct Release(Arc<Turn>); 
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-        // Try running with: IGNORE_CASE=1 cargo run -- to poem.txt
-        // to unset: unset IGNORE_CASE
-    } else {
-        search(&config.query, &contents)
-    }; 
+impl Drop for Release {
+    fn drop(&mut self) {
+        self.0.set(usize::MAX); 
+    }
+}
+
+// Where a worker writes the output of one file: it's held back until there
+// is `JOB_BUFFER_LEN` of it, then handed on once it's the file's turn
+struct JobOutput {
+    index: usize, 
+    buffer: Vec<u8>, 
+    sender: mpsc::Sender<FileResult>, 
+    turn: Arc<Turn>, 
+}
+
+impl Write for JobOutput {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes); 
+        if self.buffer.len() >= JOB_BUFFER_LEN {
+            self.turn.wait_for(self.index); 
+            let output = mem::take(&mut self.buffer); 
+            self.sender.send(FileResult::Output(self.index, output)).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?; 
+        }
+        Ok(bytes.len())
+    }
+
+    // What is held back waits for the file's turn, not for a flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Every file to search, in output order. Files found by -r are flagged, since
+// those are skipped when they look binary; an unreadable directory is an error.
+fn inputs(config: &Config) -> impl Iterator<Item = io::Result<(PathBuf, bool)>> + '_ {
+    config.file_paths.iter().flat_map(move |file_path| -> Box<dyn Iterator<Item = io::Result<(PathBuf, bool)>>> {
+        if !config.recursive || file_path == "-" {
+            return Box::new(std::iter::once(Ok((PathBuf::from(file_path), false)))); 
+        }
+        let filter = walk::Filter {
+            include: config.include.iter().map(|pattern| Glob::new(pattern)).collect(), 
+            exclude: config.exclude.iter().map(|pattern| Glob::new(pattern)).collect(), 
+            hidden: config.hidden, 
+            no_ignore: config.no_ignore, 
+        }; 
+        Box::new(Walk::new(file_path, filter).map(|path| path.map(|path| (path, true))))
+    })
+}
 
-    for line in results {
-        println!("{line}")
+// Searches one file, or the standard input for `-`
+fn search_path(config: &Config, out: &mut impl Write, path: &Path, skip_binary: bool, find: &Finder) -> io::Result<usize> {
+    if path == Path::new("-") {
+        let (encoding, reader) = decoded(io::stdin().lock())?; 
+        report(stream::STDIN_NAME, encoding); 
+        return stream::search_reader(reader, out, config, None, find); 
     }
+    let display = path.display().to_string(); 
+    // Read whole, since the file is replaced once it has been searched
+    if let (Some(suffix), Some(_)) = (&config.in_place, &config.replace) {
+        let original = fs::read(path)?; 
+        if skip_binary && walk::is_binary(&original) {
+            return Ok(0); 
+        }
+        if inflate::Format::detect(&original).is_some_and(|format| format.inflates(&original)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "compressed files can't be rewritten in place")); 
+        }
+        if let (encoding @ (Encoding::Utf16Le | Encoding::Utf16Be), _) = Encoding::detect(&original) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{encoding} files can't be rewritten in place"))); 
+        }
+        let mut rewritten = Vec::with_capacity(original.len()); 
+        let selected = stream::rewrite_reader(&original[..], out, &mut rewritten, config, Some(&display), find)?; 
+        if rewritten != original {
+            replace::write_back(path, &rewritten, suffix)?; 
+        }
+        return Ok(selected); 
+    }
+    // Binary or not is decided on the bytes as they are, before UTF-16 is decoded
+    let mut reader = decompressed(BufReader::new(File::open(path)?))?; 
+    if skip_binary && encoding::is_binary(reader.fill_buf()?) {
+        return Ok(0); 
+    }
+    let (encoding, reader) = encoding::decode(reader)?; 
+    report(&display, encoding); 
+    stream::search_reader(reader, out, config, Some(&display), find)
+}
 
-    Ok(())
+// Says which encoding was assumed for an input in UTF-16. Latin-1 is noted by
+// the stream, at the first line that isn't UTF-8.
+fn report(name: &str, encoding: Encoding) {
+    if let Encoding::Utf16Le | Encoding::Utf16Be = encoding {
+        eprintln!("minigrep: {name}: searched as {encoding}"); 
+    }
+}
+
+// Decompressed, then converted to UTF-8 when it is UTF-16
+fn decoded<'a>(reader: impl BufRead + 'a) -> io::Result<(Encoding, Box<dyn BufRead + 'a>)> {
+    encoding::decode(decompressed(reader)?)
+}
+
+// gzip and zlib input is decompressed as it is read, recognised by its first
+// bytes; when those don't decompress after all, the input is searched as it is
+fn decompressed<'a>(mut reader: impl BufRead + 'a) -> io::Result<Box<dyn BufRead + 'a>> {
+    let start = reader.fill_buf()?; 
+    Ok(match inflate::Format::detect(start).filter(|format| format.inflates(start)) {
+        Some(format) => Box::new(BufReader::new(inflate::Decoder::new(reader, format))), 
+        None => Box::new(reader), 
+    })
 }
 
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
@@ -91,28 +420,99 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     // results 
 
     // Body of Chapter 13: 
+    // contents 
+    //     .lines()
+    //     .filter(|line| line.contains(query))
+    //     .collect()
+
+    Searcher::default().lines(&Literal::new(query), contents)
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    // Body of Chapter 12: 
+    // let query = query.to_lowercase(); 
+    // let mut results = Vec::new(); 
+    // for line in contents.lines() {
+    //     if line.to_lowercase().contains(&query) {
+    //         results.push(line); 
+    //     }
+    // }
+    // results
+
+    // Case folding instead of `to_lowercase`: only the query is converted, and
+    // characters like 'ς' or 'ſ' match their other forms too
+    Searcher::default().lines(&CaseInsensitive::new(query), contents)
+}
+
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<&'a str> {
     contents 
         .lines()
-        .filter(|line| line.contains(query))
+        .filter(|line| regex.is_match(line))
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase(); 
-    let mut results = Vec::new(); 
+pub fn search_patterns<'a>(automaton: &AhoCorasick, contents: &'a str) -> Vec<&'a str> {
+    contents 
+        .lines()
+        .filter(|line| !automaton.find_spans(line).is_empty())
+        .collect()
+}
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line); 
-        }
-    }
-    results
+// The lines containing `query`, as they'd read with every occurrence replaced by `with`
+pub fn search_replace(query: &str, contents: &str, with: &str) -> Vec<String> {
+    search_matches(query, contents) 
+        .iter()
+        .map(|found| replace::apply(found.line, &found.spans, with).0)
+        .collect()
+}
+
+// The lines within `max_distance` typos of `query`, with how many it took
+pub fn search_fuzzy<'a>(query: &str, contents: &'a str, max_distance: usize) -> Vec<(&'a str, usize)> {
+    let matcher = Fuzzy::new(query, max_distance, false); 
+    contents 
+        .lines()
+        .filter_map(|line| matcher.find(line).map(|(_, distance)| (line, distance)))
+        .collect()
+}
+
+pub fn search_with_context<'a>(query: &str, contents: &'a str, before: usize, after: usize) -> Vec<Vec<ContextLine<'a>>> {
+    let selected = context::selected_lines(contents, &search(query, contents)); 
+    let lines: Vec<&str> = contents.lines().collect(); 
+    context::groups(&lines, &selected, before, after)
+}
+
+pub fn search_case_insensitive_with_context<'a>(query: &str, contents: &'a str, before: usize, after: usize) -> Vec<Vec<ContextLine<'a>>> {
+    let selected = context::selected_lines(contents, &search_case_insensitive(query, contents)); 
+    let lines: Vec<&str> = contents.lines().collect(); 
+    context::groups(&lines, &selected, before, after)
+}
+
+pub fn search_matches<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    matches::collect(contents, |line| matches::literal_spans(query, line))
+}
+
+pub fn search_matches_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    let query = casefold::fold_str(query); 
+    matches::collect(contents, |line| matches::case_insensitive_spans(&query, line))
+}
+
+pub fn search_matches_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<Match<'a>> {
+    matches::collect(contents, |line| matches::regex_spans(regex, line))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*; 
 
+    // An empty directory of its own for the files of a test, under the system
+    // temp directory; the tests of other modules use it too
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("minigrep-{name}-{}", std::process::id())); 
+        let _ = fs::remove_dir_all(&dir); 
+        fs::create_dir_all(&dir).unwrap(); 
+        dir
+    }
+
     #[test]
     fn case_sensitive() {
         let query = "duct"; 
@@ -130,4 +530,292 @@ mod tests {
 
         assert_eq!(vec!["Rust:", "Trust me."], search_case_insensitive(query, contents)); 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let query = "ΣΟΦΟΣ"; 
+        let contents = "\
+Здравствуйте, σοφός\nσοφος\nΣΟΦΟ\nὁ σοφὸς"; 
+
+        assert_eq!(vec!["σοφος"], search_case_insensitive(query, contents)); 
+        assert_eq!(vec!["Здравствуйте, σοφός"], search_case_insensitive("здравствуйте", contents)); 
+    }
+
+    #[test]
+    fn regex() {
+        let regex = Regex::build(r"^\w+ (\d{3}|-)$", false).unwrap(); 
+        let contents = "\
+GET 200\nPOST -\nGET 20\nerror: 500"; 
+
+        assert_eq!(vec!["GET 200", "POST -"], search_regex(&regex, contents)); 
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let regex = Regex::build("t(ape|hree)", true).unwrap(); 
+        let contents = "\
+Rust:\nsafe, fast, productive.\nPick THREE.\nDuct tape."; 
+
+        assert_eq!(vec!["Pick THREE.", "Duct tape."], search_regex(&regex, contents)); 
+    }
+
+    #[test]
+    fn several_patterns() {
+        let automaton = AhoCorasick::new(&["three", "TRUST", "duct"], false); 
+        let contents = "\
+Rust:\nsafe, fast, productive.\nPick three.\nTrust me."; 
+
+        assert_eq!(vec!["safe, fast, productive.", "Pick three."], search_patterns(&automaton, contents)); 
+        let automaton = AhoCorasick::new(&["three", "TRUST"], true); 
+        assert_eq!(vec!["Pick three.", "Trust me."], search_patterns(&automaton, contents)); 
+    }
+
+    #[test]
+    fn several_regexes() {
+        let patterns = vec![r"\d+".to_string(), "a|b".to_string()]; 
+        let regex = Regex::build(&alternatives(&patterns, false).unwrap(), false).unwrap(); 
+        assert_eq!(vec!["x1", "b"], search_regex(&regex, "x1\nb\nc")); 
+
+        let patterns = vec!["ok".to_string(), "(oops".to_string()]; 
+        assert!(matches!(alternatives(&patterns, false), Err(RegexError::UnclosedGroup(0)))); 
+    }
+
+    #[test]
+    fn whole_words_and_lines() {
+        let spans = |patterns: &[&str], options: Config| {
+            let config = Config { patterns: patterns.iter().map(|p| p.to_string()).collect(), ..options }; 
+            let find = finder(&config).unwrap(); 
+            ["Rust is trusty", "rust", "Rust, RUST!"].map(|line| find(line))
+        }; 
+
+        let words = Config { word_regexp: true, ignore_case: true, ..Config::default() }; 
+        assert_eq!([vec![(0, 4)], vec![(0, 4)], vec![(0, 4), (6, 10)]], spans(&["rust"], words)); 
+        let words = Config { word_regexp: true, ..Config::default() }; 
+        assert_eq!([vec![(5, 7)], vec![], vec![(6, 10)]], spans(&["RUST", "is", "trust"], words)); 
+        let words = Config { word_regexp: true, regex: true, ..Config::default() }; 
+        assert_eq!([vec![(8, 14)], vec![], vec![]], spans(&["t[a-z]+"], words)); 
+        // `Ru` is cut off by the rest of the word, `Rust` isn't
+        let words = Config { word_regexp: true, regex: true, ..Config::default() }; 
+        assert_eq!([vec![(0, 4)], vec![], vec![(0, 4)]], spans(&["Ru|Rust"], words)); 
+
+        let lines = Config { line_regexp: true, word_regexp: true, ignore_case: true, ..Config::default() }; 
+        assert_eq!([vec![], vec![(0, 4)], vec![]], spans(&["rust"], lines)); 
+        let lines = Config { line_regexp: true, regex: true, ..Config::default() }; 
+        assert_eq!([vec![(0, 14)], vec![], vec![(0, 11)]], spans(&["R.*", "Ru"], lines)); 
+        // The closest fuzzy occurrence, `rust` in "trusty", isn't a word; `Rust` is
+        let words = Config { word_regexp: true, fuzzy: Some(1), ..Config::default() }; 
+        assert_eq!([vec![(0, 4)], vec![(0, 4)], vec![(0, 4)]], spans(&["rust"], words)); 
+        let lines = Config { line_regexp: true, fuzzy: Some(1), ..Config::default() }; 
+        assert_eq!([vec![], vec![(0, 4)], vec![]], spans(&["rust"], lines)); 
+    }
+
+    #[test]
+    fn replace() {
+        let contents = "\
+Rust:\nsafe, fast, productive.\nPick three.\nDuct tape, product."; 
+
+        assert_eq!(vec!["safe, fast, proDUCTive.", "Duct tape, proDUCT."], search_replace("duct", contents, "DUCT")); 
+    }
+
+    #[test]
+    fn rewrite_in_place() {
+        let dir = scratch_dir("in-place"); 
+        let (changed, unchanged) = (dir.join("changed.txt"), dir.join("unchanged.txt")); 
+        fs::write(&changed, "let old = 1;\r\nold(old);\n").unwrap(); 
+        fs::write(&unchanged, "nothing here\n").unwrap(); 
+        let config = Config {
+            file_paths: vec![changed.display().to_string(), unchanged.display().to_string()], 
+            replace: Some("new".to_string()), 
+            in_place: Some(".orig".to_string()), 
+            ..Config::default()
+        }; 
+
+        let find: Arc<Finder> = Arc::new(|line: &str| matches::literal_spans("old", line)); 
+        assert_eq!(Status::Success, search_files(Arc::new(config), &mut Vec::new(), find).unwrap()); 
+        assert_eq!("let new = 1;\r\nnew(new);\n", fs::read_to_string(&changed).unwrap()); 
+        assert_eq!("let old = 1;\r\nold(old);\n", fs::read_to_string(dir.join("changed.txt.orig")).unwrap()); 
+        // Files without a match are left alone, without a backup
+        assert!(!dir.join("unchanged.txt.orig").exists()); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn fuzzy() {
+        let contents = "\
+[server]\nconfig = true\nconifg = false\ncnfg = 1"; 
+
+        // Swapped letters are two typos
+        assert_eq!(vec![("config = true", 0), ("conifg = false", 2), ("cnfg = 1", 2)], search_fuzzy("config", contents, 2)); 
+        assert_eq!(vec![("config = true", 0)], search_fuzzy("config", contents, 1)); 
+    }
+
+    #[test]
+    fn compressed_files() {
+        let config = Config { line_number: true, ..Config::default() }; 
+        let find: Box<Finder> = Box::new(|line: &str| matches::literal_spans("How", line)); 
+        for fixture in ["poem.txt.gz", "poem.txt.zz", "poem.txt"] {
+            let mut out = Vec::new(); 
+            assert_eq!(2, search_path(&config, &mut out, Path::new(fixture), true, &*find).unwrap()); 
+            assert_eq!("6:How dreary to be somebody!\n7:How public, like a frog\n", String::from_utf8(out).unwrap()); 
+        }
+    }
+
+    #[test]
+    fn other_encodings() {
+        let dir = scratch_dir("encodings"); 
+        let mut utf16 = vec![0xff, 0xfe]; 
+        utf16.extend("Event 1: ok\r\nEvent 2: disk café full\r\n".encode_utf16().flat_map(u16::to_le_bytes)); 
+        fs::write(dir.join("utf16.log"), utf16).unwrap(); 
+        fs::write(dir.join("latin1.log"), b"Event 1: ok\nEvent 2: disk caf\xe9 full\n").unwrap(); 
+        // Starts like a zlib header
+        fs::write(dir.join("xu.log"), b"x\xdanico 1: ok\nEvent 2: disk caf\xe9 full\n").unwrap(); 
+
+        let config = Config { line_number: true, ..Config::default() }; 
+        let find: Box<Finder> = Box::new(|line: &str| matches::literal_spans("café", line)); 
+        for file in ["utf16.log", "latin1.log", "xu.log"] {
+            let mut out = Vec::new(); 
+            assert_eq!(1, search_path(&config, &mut out, &dir.join(file), true, &*find).unwrap()); 
+            assert_eq!("2:Event 2: disk café full\n", String::from_utf8(out).unwrap()); 
+        }
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn context() {
+        let query = "duct"; 
+        let contents = "\
+Rust:\nsafe, fast, productive.\nPick three.\nDuct tape.\nTrust me.\nproduction"; 
+
+        let groups = search_with_context(query, contents, 1, 0); 
+        let lines: Vec<Vec<&str>> = groups.iter().map(|group| group.iter().map(|line| line.line).collect()).collect(); 
+        assert_eq!(vec![vec!["Rust:", "safe, fast, productive."], vec!["Trust me.", "production"]], lines); 
+
+        let groups = search_case_insensitive_with_context(query, contents, 0, 1); 
+        assert_eq!(1, groups.len()); 
+        assert_eq!(vec![2, 3, 4, 5, 6], groups[0].iter().map(|line| line.number).collect::<Vec<_>>()); 
+    }
+
+    #[test]
+    fn structured_matches() {
+        let contents = "\
+Rust:\nsafe, fast, productive.\nPick three.\nTrust me."; 
+
+        let matches = search_matches_case_insensitive("rust", contents); 
+        assert_eq!(vec![1, 4], matches.iter().map(|found| found.line_number).collect::<Vec<_>>()); 
+        assert_eq!(vec![0, 42], matches.iter().map(|found| found.byte_offset).collect::<Vec<_>>()); 
+        assert_eq!(vec![(1, 5)], matches[1].spans); 
+
+        let regex = Regex::build("[a-z]*st", false).unwrap(); 
+        let matches = search_matches_regex(&regex, contents); 
+        let matched: Vec<&str> = matches.iter().flat_map(|found| found.matched()).collect(); 
+        assert_eq!(vec!["ust", "fast", "rust"], matched); 
+        assert_eq!(search(".", contents).len(), search_matches(".", contents).len()); 
+    }
+
+    #[test]
+    fn several_files_keep_their_order() {
+        let dir = scratch_dir("files"); 
+        let mut file_paths = Vec::new(); 
+        for i in 0..20 {
+            let path = dir.join(format!("{i}.txt")); 
+            // Bigger files first, so they tend to finish last
+            fs::write(&path, "skip\n".repeat(2000 - i * 100) + &format!("match {i}\n")).unwrap(); 
+            file_paths.push(path.display().to_string()); 
+        }
+        file_paths.push(dir.join("missing.txt").display().to_string()); 
+        let config = Config { file_paths: file_paths.clone(), with_filename: true, threads: 4, ..Config::default() }; 
+
+        let mut out = Vec::new(); 
+        let find: Arc<Finder> = Arc::new(|line: &str| matches::literal_spans("match", line)); 
+        // The missing file makes it an error, despite the matches
+        assert_eq!(Status::Error, search_files(Arc::new(config), &mut out, find).unwrap()); 
+
+        let expected: String = (0..20).map(|i| format!("{}:match {i}\n", file_paths[i])).collect(); 
+        assert_eq!(expected, String::from_utf8(out).unwrap()); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn long_outputs_keep_their_order() {
+        let dir = scratch_dir("long-outputs"); 
+        let mut file_paths = Vec::new(); 
+        let mut expected = String::new(); 
+        for i in 0..6 {
+            let path = dir.join(format!("{i}.txt")); 
+            // Several times what a worker holds on to before it waits for its turn
+            let contents: String = (0..3000).map(|line| format!("match {i}.{line} {}\n", "-".repeat(80))).collect(); 
+            fs::write(&path, &contents).unwrap(); 
+            expected += &contents; 
+            file_paths.push(path.display().to_string()); 
+        }
+        let config = Config { file_paths, threads: 3, ..Config::default() }; 
+
+        let mut out = Vec::new(); 
+        let find: Arc<Finder> = Arc::new(|line: &str| matches::literal_spans("match", line)); 
+        assert_eq!(Status::Success, search_files(Arc::new(config), &mut out, find).unwrap()); 
+        assert_eq!(expected, String::from_utf8(out).unwrap()); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn a_panicking_search_is_an_error_for_its_file() {
+        let dir = scratch_dir("panic"); 
+        let mut file_paths = Vec::new(); 
+        for (name, contents) in [("a.txt", "match a\n"), ("b.txt", "boom\n"), ("c.txt", "match c\n")] {
+            fs::write(dir.join(name), contents).unwrap(); 
+            file_paths.push(dir.join(name).display().to_string()); 
+        }
+        let config = Config { file_paths, threads: 2, ..Config::default() }; 
+
+        let mut out = Vec::new(); 
+        let find: Arc<Finder> = Arc::new(|line: &str| {
+            assert!(line != "boom", "a matcher that gives up"); 
+            matches::literal_spans("match", line)
+        }); 
+        assert_eq!(Status::Error, search_files(Arc::new(config), &mut out, find).unwrap()); 
+        assert_eq!("match a\nmatch c\n", String::from_utf8(out).unwrap()); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn binary_files_are_skipped_when_recursing() {
+        let dir = scratch_dir("binary"); 
+        // A NUL in every odd byte, like UTF-16LE, but with control characters among them
+        let binary: Vec<u8> = "match".bytes().chain(1..=20).flat_map(|byte| [byte, 0]).collect(); 
+        fs::write(dir.join("data.bin"), binary).unwrap(); 
+        let text: Vec<u8> = "a match\n".encode_utf16().flat_map(u16::to_le_bytes).collect(); 
+        fs::write(dir.join("notes.txt"), text).unwrap(); 
+        // Control characters after a byte order mark aren't UTF-16 text either
+        let with_bom: Vec<u8> = [0xff, 0xfe].into_iter().chain("match".bytes().chain(1..=20).flat_map(|byte| [byte, 0])).collect(); 
+        fs::write(dir.join("with-bom.bin"), with_bom).unwrap(); 
+        let config = Config { file_paths: vec![dir.display().to_string()], recursive: true, with_filename: true, ..Config::default() }; 
+
+        let mut out = Vec::new(); 
+        let find: Arc<Finder> = Arc::new(|line: &str| matches::literal_spans("match", line)); 
+        search_files(Arc::new(config), &mut out, find).unwrap(); 
+        assert_eq!(format!("{}:a match\n", dir.join("notes.txt").display()), String::from_utf8(out).unwrap()); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+
+    #[test]
+    fn exit_status() {
+        let dir = scratch_dir("status"); 
+        let with = dir.join("with.txt").display().to_string(); 
+        let without = dir.join("without.txt").display().to_string(); 
+        fs::write(&with, "a match\n").unwrap(); 
+        fs::write(&without, "nothing\n").unwrap(); 
+        let status = |file_paths: &[&String], files_without_match: bool| {
+            let file_paths = file_paths.iter().map(|path| path.to_string()).collect(); 
+            let config = Config { file_paths, files_without_match, ..Config::default() }; 
+            let find: Arc<Finder> = Arc::new(|line: &str| matches::literal_spans("match", line)); 
+            search_files(Arc::new(config), &mut Vec::new(), find).unwrap()
+        }; 
+
+        assert_eq!(Status::Success, status(&[&with], false)); 
+        assert_eq!(Status::NoMatch, status(&[&without], false)); 
+        assert_eq!(Status::Success, status(&[&without, &with], false)); 
+        assert_eq!(Status::Success, status(&[&without], true)); 
+        assert_eq!(Status::NoMatch, status(&[&with], true)); 
+        fs::remove_dir_all(dir).unwrap(); 
+    }
+}
@@ -11,7 +11,7 @@ fn main() {
     // let config = Config::build(&args).unwrap_or_else(|err| {
     let config = Config::build(env::args()).unwrap_or_else(|err| { // -> change after chapter 13 
         eprintln!("Problem parsing arguments: {err}"); // eprintln! prints to stderr // e.g. cargo run 2> output.log or cargo run > output.log 2>&1 
-        process::exit(1); 
+        process::exit(2); 
     }); 
 
     // println!("Searching for {}", config.query); 
@@ -21,10 +21,19 @@ fn main() {
 
     // println!("With text:\n{contents}");
 
-    if let Err(e) = minigrep::run(config) {   // no need for unwrap_or_else here as Ok() is () in run()
-        eprintln!("Application error: {e}"); 
-        process::exit(1); 
-    } 
+    // if let Err(e) = minigrep::run(config) {   // no need for unwrap_or_else here as Ok() is () in run()
+    //     eprintln!("Application error: {e}"); 
+    //     process::exit(1); 
+    // } 
+
+    // Exit codes like grep's, for scripts: 0 found, 1 not found, 2 error
+    match minigrep::run(config) {
+        Ok(status) => process::exit(status.code()), 
+        Err(e) => {
+            eprintln!("Application error: {e}"); 
+            process::exit(2); 
+        }
+    }
 
 }
 
@@ -0,0 +1,269 @@
+// Where the query occurs, not just which lines contain it.
+//
+// A span is the byte range `(start, end)` of one occurrence inside its line;
+// occurrences never overlap and are listed from left to right.
+
+use crate::casefold;
+use crate::regex::Regex;
+
+pub type Span = (usize, usize);
+
+// Combining marks that aren't alphanumeric, so `-w` doesn't split words at
+// them: the generic combining blocks, zero-width joiners, and the viramas and
+// signs of the most common Brahmic scripts.
+const WORD_MARKS: &[(char, char)] = &[
+    ('\u{300}', '\u{36f}'),   // Combining Diacritical Marks
+    ('\u{483}', '\u{489}'),   // Cyrillic titlo and friends
+    ('\u{591}', '\u{5c7}'),   // Hebrew points
+    ('\u{610}', '\u{61a}'),   // Arabic
+    ('\u{64b}', '\u{65f}'),
+    ('\u{670}', '\u{670}'),
+    ('\u{900}', '\u{903}'),   // Devanagari
+    ('\u{93a}', '\u{94f}'),
+    ('\u{951}', '\u{957}'),
+    ('\u{962}', '\u{963}'),
+    ('\u{981}', '\u{983}'),   // Bengali
+    ('\u{9bc}', '\u{9d7}'),
+    ('\u{a01}', '\u{a03}'),   // Gurmukhi
+    ('\u{a3c}', '\u{a51}'),
+    ('\u{abc}', '\u{acd}'),   // Gujarati
+    ('\u{b3c}', '\u{b57}'),   // Oriya
+    ('\u{bbe}', '\u{bd7}'),   // Tamil
+    ('\u{c3c}', '\u{c56}'),   // Telugu
+    ('\u{cbc}', '\u{cd6}'),   // Kannada
+    ('\u{d3b}', '\u{d57}'),   // Malayalam
+    ('\u{dca}', '\u{ddf}'),   // Sinhala
+    ('\u{e31}', '\u{e3a}'),   // Thai
+    ('\u{e47}', '\u{e4e}'),
+    ('\u{1ab0}', '\u{1aff}'), // Combining Diacritical Marks Extended
+    ('\u{1dc0}', '\u{1dff}'), // Combining Diacritical Marks Supplement
+    ('\u{200c}', '\u{200d}'), // ZERO WIDTH NON-JOINER and JOINER
+    ('\u{20d0}', '\u{20ff}'), // Combining Diacritical Marks for Symbols
+    ('\u{fe00}', '\u{fe0f}'), // variation selectors
+    ('\u{fe20}', '\u{fe2f}'), // Combining Half Marks
+];
+
+/// What an occurrence has to line up with: nothing, whole words (`-w`) or the
+/// whole line (`-x`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Boundary {
+    #[default]
+    None,
+    Word,
+    Line,
+}
+
+impl Boundary {
+    pub fn accepts(self, line: &str, (start, end): Span) -> bool {
+        match self {
+            Boundary::None => true,
+            // Like grep: no word character right before or right after
+            Boundary::Word => {
+                !line[..start].chars().next_back().is_some_and(is_word_char)
+                    && !line[end..].chars().next().is_some_and(is_word_char)
+            }
+            Boundary::Line => start == 0 && end == line.len(),
+        }
+    }
+}
+
+/// Letters and digits of any script, `_`, and the marks that combine with them.
+pub fn is_word_char(c: char) -> bool {
+    if c.is_alphanumeric() || c == '_' {
+        return true;
+    }
+    let i = WORD_MARKS.partition_point(|&(_, last)| last < c);
+    WORD_MARKS.get(i).is_some_and(|&(first, _)| first <= c)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    pub line_number: usize, // 1-based
+    pub byte_offset: usize, // of the start of the line, from the start of the input
+    pub line: &'a str,
+    pub spans: Vec<Span>,
+}
+
+impl<'a> Match<'a> {
+    /// The matched text of every occurrence, as printed by `-o`.
+    pub fn matched(&self) -> impl Iterator<Item = &'a str> + '_ {
+        let line = self.line;
+        self.spans.iter().map(move |&(start, end)| &line[start..end])
+    }
+}
+
+/// Runs `find` over every line of `contents`, keeping the lines where it found something.
+pub fn collect<'a>(contents: &'a str, find: impl Fn(&str) -> Vec<Span>) -> Vec<Match<'a>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let spans = find(line);
+            if spans.is_empty() {
+                return None;
+            }
+            Some(Match {
+                line_number: i + 1,
+                // `lines` hands out sub-slices of `contents`, so the pointers tell where the line starts
+                byte_offset: line.as_ptr() as usize - contents.as_ptr() as usize,
+                line,
+                spans,
+            })
+        })
+        .collect()
+}
+
+pub fn literal_spans(query: &str, line: &str) -> Vec<Span> {
+    // An empty query matches every line, without anything to show for `-o`
+    if query.is_empty() {
+        return vec![(0, 0)];
+    }
+    line.match_indices(query).map(|(start, found)| (start, start + found.len())).collect()
+}
+
+/// `query_folded` comes from `casefold::fold_str`. Spans refer to `line` itself.
+pub fn case_insensitive_spans(query_folded: &[char], line: &str) -> Vec<Span> {
+    if query_folded.is_empty() {
+        return vec![(0, 0)];
+    }
+    let mut spans = Vec::new();
+    let mut next_start = 0;
+    for (start, _) in line.char_indices() {
+        if start < next_start {
+            continue;
+        }
+        if let Some(len) = casefold::prefix_len(&line[start..], query_folded) {
+            spans.push((start, start + len));
+            next_start = start + len;
+        }
+    }
+    spans
+}
+
+/// The occurrences that `boundary` accepts. `find_at(line, pos)` returns the
+/// first occurrence starting at `pos` or later; after a rejected one, the search
+/// goes on from the next character, so overlapping candidates get their turn.
+///
+/// Only one candidate per start is ever tried, which is right for a fixed
+/// string but not for a regex: `ab|abc` would stop at `ab` in `abc` and never
+/// see `abc`. Regexes check the edges themselves, with `Regex::build_words`.
+pub fn bounded_spans(line: &str, boundary: Boundary, find_at: impl Fn(&str, usize) -> Option<Span>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    while let Some((start, end)) = find_at(line, pos) {
+        if boundary.accepts(line, (start, end)) {
+            spans.push((start, end));
+            if end > start {
+                pos = end;
+                continue;
+            }
+        }
+        // Step over one character, after an empty occurrence or a rejected one
+        match line[start..].chars().next() {
+            Some(c) => pos = start + c.len_utf8(),
+            None => break,
+        }
+    }
+    spans
+}
+
+pub fn literal_at(query: &str, line: &str, pos: usize) -> Option<Span> {
+    line[pos..].find(query).map(|start| (pos + start, pos + start + query.len()))
+}
+
+pub fn case_insensitive_at(query_folded: &[char], line: &str, pos: usize) -> Option<Span> {
+    line[pos..]
+        .char_indices()
+        .map(|(i, _)| pos + i)
+        .chain(std::iter::once(line.len()))
+        .find_map(|start| casefold::prefix_len(&line[start..], query_folded).map(|len| (start, start + len)))
+}
+
+pub fn regex_spans(regex: &Regex, line: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    while let Some((start, end)) = regex.find_at(line, pos) {
+        spans.push((start, end));
+        if end > start {
+            pos = end;
+        } else {
+            // Step over one character after an empty match, or we'd find it forever
+            match line[end..].chars().next() {
+                Some(c) => pos = end + c.len_utf8(),
+                None => break,
+            }
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_and_numbers() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nDuct tape, product.";
+        let matches = collect(contents, |line| literal_spans("duct", line));
+
+        assert_eq!(2, matches.len());
+        assert_eq!((2, 7, "safe, fast, productive."), (matches[0].line_number, matches[0].byte_offset, matches[0].line));
+        assert_eq!(vec![(15, 19)], matches[0].spans);
+        assert_eq!((4, 43), (matches[1].line_number, matches[1].byte_offset));
+        assert_eq!(vec!["duct"], matches[1].matched().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn case_insensitive_spans_in_the_original_line() {
+        let spans = |query: &str, line: &str| case_insensitive_spans(&casefold::fold_str(query), line);
+
+        assert_eq!(vec![(0, 4), (10, 14)], spans("rust", "Rust and TRUST"));
+        // 'İ' is two bytes and folds to itself
+        assert_eq!(vec![(5, 6)], spans("x", "İİ-X"));
+        assert_eq!(Vec::<Span>::new(), spans("i", "İ"));
+        // 'ſ' (two bytes) folds to 's'
+        assert_eq!(vec![(0, 5)], spans("sst", "ſſt"));
+    }
+
+    #[test]
+    fn word_characters() {
+        assert!("Straße_42".chars().all(is_word_char));
+        assert!("नमस्ते".chars().all(is_word_char)); // with a virama
+        assert!("re\u{301}sume\u{301}".chars().all(is_word_char)); // decomposed accents
+        assert!("日本語".chars().all(is_word_char));
+        assert!(!" -,.!¿—'\"\u{a0}".chars().any(is_word_char));
+    }
+
+    #[test]
+    fn whole_words() {
+        let words = |query: &str, line: &str| bounded_spans(line, Boundary::Word, |line, pos| literal_at(query, line, pos));
+
+        assert_eq!(vec![(0, 2), (10, 12)], words("to", "to tomato to"));
+        assert_eq!(Vec::<Span>::new(), words("to", "toto"));
+        // The first candidate fails, the overlapping one after it doesn't
+        assert_eq!(vec![(4, 6)], words("aa", "aaa aa"));
+        assert_eq!(vec![(4, 14)], words("σοφός", "ὁ σοφός, σοφόςx"));
+        assert_eq!(Vec::<Span>::new(), words("resume", "re\u{301}sume"));
+
+        let folded = casefold::fold_str("RUST");
+        let spans = bounded_spans("Rust, rusty TRUST rust", Boundary::Word, |line, pos| case_insensitive_at(&folded, line, pos));
+        assert_eq!(vec![(0, 4), (18, 22)], spans);
+    }
+
+    #[test]
+    fn whole_lines() {
+        let lines = |query: &str, line: &str| bounded_spans(line, Boundary::Line, |line, pos| literal_at(query, line, pos));
+
+        assert_eq!(vec![(0, 4)], lines("rust", "rust"));
+        assert_eq!(Vec::<Span>::new(), lines("rust", "rust "));
+        assert_eq!(vec![(0, 0)], lines("", ""));
+        assert_eq!(Vec::<Span>::new(), lines("", "x"));
+    }
+
+    #[test]
+    fn regex_spans_step_over_empty_matches() {
+        let regex = Regex::build(r"\d*", false).unwrap();
+
+        assert_eq!(vec![(0, 2), (2, 2), (3, 4), (4, 4)], regex_spans(&regex, "12a3"));
+    }
+}
@@ -0,0 +1,106 @@
+// The worker pool used to search several files at once.
+//
+// Same design as the `ThreadPool` of web_server_project: a fixed number of
+// workers pull boxed jobs from a shared channel, and dropping the pool waits
+// for the queued jobs to finish. Workers stay quiet, since their output would
+// get mixed with the search results.
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::with_capacity(size);
+        for _ in 0..size {
+            workers.push(Worker::new(Arc::clone(&receiver)));
+        }
+
+        ThreadPool { workers, sender: Some(sender) }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::spawn(move || loop {
+            let message = receiver.lock().unwrap().recv();
+
+            match message {
+                Ok(job) => job(),
+                Err(_) => break, // the pool was dropped
+            }
+        });
+        Worker { thread: Some(thread) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_every_job_before_drop_returns() {
+        let (sender, receiver) = mpsc::channel();
+        let pool = ThreadPool::new(3);
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap());
+        }
+        drop(pool);
+        drop(sender);
+
+        let mut done: Vec<i32> = receiver.iter().collect();
+        done.sort();
+        assert_eq!((0..10).collect::<Vec<_>>(), done);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_workers() {
+        ThreadPool::new(0);
+    }
+}
@@ -0,0 +1,743 @@
+// A small regular-expression engine for the `-E` mode.
+//
+// Patterns are parsed into an AST, compiled into a list of instructions and
+// executed with a Pike VM (a breadth-first simulation of the NFA), so matching
+// time is linear in the length of the line, whatever the pattern looks like.
+//
+// Supported syntax:
+//   literals, `.`, `[abc]`, `[^a-z]`, `\d \w \s \D \W \S`
+//   anchors `^` and `$`, word boundaries `\b` and `\B`
+//   alternation `a|b`, groups `(ab)` and `(?:ab)`
+//   repetition `* + ? {n} {n,} {n,m}` (append `?` for the lazy version)
+
+use std::error::Error;
+use std::fmt;
+
+use crate::casefold;
+use crate::matches::is_word_char;
+
+// Upper bound for `{n,m}` counts, which are compiled by copying the repeated node
+const MAX_REPEAT: u32 = 1000;
+// Upper bound for the compiled program, since nested counts multiply: `(a{1000}){1000}`
+// is a million instructions
+const MAX_PROG_LEN: u64 = 100_000;
+// Upper bound for nested groups and repetitions, since parsing and compiling
+// recurse into them: `((((...))))` would otherwise overflow the stack
+const MAX_DEPTH: usize = 250;
+
+#[derive(Debug, PartialEq)]
+pub enum RegexError {
+    UnmatchedParen(usize),
+    UnclosedGroup(usize),
+    UnclosedClass(usize),
+    InvalidRange(usize),
+    DanglingEscape(usize),
+    NothingToRepeat(usize),
+    RepeatTooLarge(usize),
+    UnknownEscape(usize),
+    TooDeep(usize),
+    TooBig,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegexError::UnmatchedParen(pos) => write!(f, "unmatched ')' at position {pos}"),
+            RegexError::UnclosedGroup(pos) => write!(f, "unclosed group opened at position {pos}"),
+            RegexError::UnclosedClass(pos) => write!(f, "unclosed character class opened at position {pos}"),
+            RegexError::InvalidRange(pos) => write!(f, "invalid character range at position {pos}"),
+            RegexError::DanglingEscape(pos) => write!(f, "trailing backslash at position {pos}"),
+            RegexError::NothingToRepeat(pos) => write!(f, "nothing to repeat at position {pos}"),
+            RegexError::RepeatTooLarge(pos) => {
+                write!(f, "repetition count at position {pos} exceeds {MAX_REPEAT}")
+            }
+            RegexError::UnknownEscape(pos) => write!(f, "unknown escape sequence at position {pos}"),
+            RegexError::TooDeep(pos) => write!(f, "nesting at position {pos} is deeper than {MAX_DEPTH}"),
+            RegexError::TooBig => write!(f, "the pattern compiles to more than {MAX_PROG_LEN} instructions"),
+        }
+    }
+}
+
+impl Error for RegexError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word_char(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool), // the flag negates the class, e.g. `\D`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn single(item: ClassItem) -> Class {
+        Class { items: vec![item], negated: false }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+        });
+        found != self.negated
+    }
+
+    fn matches_ignore_case(&self, c: char) -> bool {
+        self.matches(c)
+            || self.matches(casefold::fold(c))
+            || c.to_lowercase().any(|l| self.matches(l))
+            || c.to_uppercase().any(|u| self.matches(u))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool), // the flag negates it, e.g. `\B`
+    // No word char right before or right after, as `-w` wants; there's no syntax for them
+    NoWordBefore,
+    NoWordAfter,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize, // of the groups and repetitions around `pos`
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn parse(pattern: &str) -> Result<Node, RegexError> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, depth: 0 };
+        let node = parser.parse_alternation()?;
+        match parser.peek() {
+            Some(_) => Err(RegexError::UnmatchedParen(parser.pos)),
+            None => Ok(node),
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.next();
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            items.push(self.parse_repeat()?);
+        }
+        match items.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Node::Concat(items)),
+        }
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, RegexError> {
+        let mut node = self.parse_atom()?;
+        let mut depth = self.depth;
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.parse_counts()? {
+                    Some(counts) => counts,
+                    None => break, // not a valid `{n,m}`: the brace is a literal
+                },
+                _ => break,
+            };
+            if self.pos == start {
+                self.next();
+            }
+            let greedy = if self.peek() == Some('?') {
+                self.next();
+                false
+            } else {
+                true
+            };
+            if matches!(node, Node::Start | Node::End | Node::WordBoundary(_) | Node::Empty) {
+                return Err(RegexError::NothingToRepeat(start));
+            }
+            depth += 1;
+            if depth > MAX_DEPTH {
+                return Err(RegexError::TooDeep(start));
+            }
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+        Ok(node)
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`; on anything else the position is left untouched.
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let start = self.pos;
+        self.next();
+        let min = self.parse_number();
+        let max = if self.peek() == Some(',') {
+            self.next();
+            self.parse_number()
+        } else {
+            min
+        };
+        let closed = self.peek() == Some('}');
+        let min = match min {
+            Some(min) if closed => min,
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        self.next();
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return Err(RegexError::RepeatTooLarge(start));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(RegexError::InvalidRange(start));
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let mut value: Option<u32> = None;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.next();
+            value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+        }
+        value
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        match self.next() {
+            Some('(') => {
+                if self.peek() == Some('?') && self.chars.get(self.pos + 1) == Some(&':') {
+                    self.pos += 2;
+                }
+                if self.depth == MAX_DEPTH {
+                    return Err(RegexError::TooDeep(start));
+                }
+                self.depth += 1;
+                let node = self.parse_alternation()?;
+                self.depth -= 1;
+                if self.next() != Some(')') {
+                    return Err(RegexError::UnclosedGroup(start));
+                }
+                Ok(node)
+            }
+            Some('[') => self.parse_class(start),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('*') | Some('+') | Some('?') => Err(RegexError::NothingToRepeat(start)),
+            // Assertions only make sense outside of a class, so they aren't escapes of their own
+            Some('\\') if matches!(self.peek(), Some('b' | 'B')) => Ok(Node::WordBoundary(self.next() == Some('B'))),
+            Some('\\') => match self.parse_escape(start)? {
+                ClassItem::Range(c, _) => Ok(Node::Char(c)),
+                perl => Ok(Node::Class(Class::single(perl))),
+            },
+            Some(c) => Ok(Node::Char(c)),
+            None => Ok(Node::Empty),
+        }
+    }
+
+    // Called right after a backslash; literal escapes come back as a one-char range.
+    // Other letters are refused, rather than quietly matching themselves, since
+    // they mean something in other engines (`\p{L}`, `\x41`, ...).
+    fn parse_escape(&mut self, start: usize) -> Result<ClassItem, RegexError> {
+        let item = match self.next() {
+            Some('d') => ClassItem::Perl(Perl::Digit, false),
+            Some('D') => ClassItem::Perl(Perl::Digit, true),
+            Some('w') => ClassItem::Perl(Perl::Word, false),
+            Some('W') => ClassItem::Perl(Perl::Word, true),
+            Some('s') => ClassItem::Perl(Perl::Space, false),
+            Some('S') => ClassItem::Perl(Perl::Space, true),
+            Some('t') => ClassItem::Range('\t', '\t'),
+            Some('n') => ClassItem::Range('\n', '\n'),
+            Some('r') => ClassItem::Range('\r', '\r'),
+            Some(c) if c.is_alphanumeric() => return Err(RegexError::UnknownEscape(start)),
+            Some(c) => ClassItem::Range(c, c),
+            None => return Err(RegexError::DanglingEscape(start)),
+        };
+        Ok(item)
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Node, RegexError> {
+        let mut class = Class { items: Vec::new(), negated: false };
+        if self.peek() == Some('^') {
+            self.next();
+            class.negated = true;
+        }
+        let mut first = true;
+        loop {
+            let item_start = self.pos;
+            let lo = match self.next() {
+                None => return Err(RegexError::UnclosedClass(start)),
+                Some(']') if !first => break,
+                Some('\\') => match self.parse_escape(item_start)? {
+                    ClassItem::Range(c, _) => c,
+                    perl => {
+                        class.items.push(perl);
+                        first = false;
+                        continue;
+                    }
+                },
+                Some(c) => c,
+            };
+            first = false;
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            if !is_range {
+                class.items.push(ClassItem::Range(lo, lo));
+                continue;
+            }
+            self.next();
+            let hi = match self.next() {
+                Some('\\') => match self.parse_escape(self.pos - 1)? {
+                    ClassItem::Range(c, _) => c,
+                    _ => return Err(RegexError::InvalidRange(item_start)),
+                },
+                Some(c) => c,
+                None => return Err(RegexError::UnclosedClass(start)),
+            };
+            if hi < lo {
+                return Err(RegexError::InvalidRange(item_start));
+            }
+            class.items.push(ClassItem::Range(lo, hi));
+        }
+        Ok(Node::Class(class))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    NoWordBefore,
+    NoWordAfter,
+    Split(usize, usize), // the first target has priority
+    Jmp(usize),
+    Match,
+}
+
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => prog.push(Inst::Char(*c)),
+        Node::Any => prog.push(Inst::Any),
+        Node::Class(class) => prog.push(Inst::Class(class.clone())),
+        Node::Start => prog.push(Inst::Start),
+        Node::End => prog.push(Inst::End),
+        Node::WordBoundary(negated) => prog.push(Inst::WordBoundary(*negated)),
+        Node::NoWordBefore => prog.push(Inst::NoWordBefore),
+        Node::NoWordAfter => prog.push(Inst::NoWordAfter),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, prog);
+            }
+        }
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, prog);
+                    break;
+                }
+                let split = prog.len();
+                prog.push(Inst::Split(split + 1, 0));
+                compile(branch, prog);
+                jumps.push(prog.len());
+                prog.push(Inst::Jmp(0));
+                let next = prog.len();
+                prog[split] = Inst::Split(split + 1, next);
+            }
+            let end = prog.len();
+            for jump in jumps {
+                prog[jump] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat { node, min, max, greedy } => {
+            for _ in 0..*min {
+                compile(node, prog);
+            }
+            match max {
+                None => {
+                    let split = prog.len();
+                    prog.push(Inst::Split(0, 0));
+                    compile(node, prog);
+                    prog.push(Inst::Jmp(split));
+                    prog[split] = split_for(*greedy, split + 1, prog.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Split(0, 0));
+                        compile(node, prog);
+                    }
+                    let end = prog.len();
+                    for split in splits {
+                        prog[split] = split_for(*greedy, split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The number of instructions `compile` turns `node` into
+fn prog_len(node: &Node) -> u64 {
+    match node {
+        Node::Empty => 0,
+        Node::Char(_) | Node::Any | Node::Class(_) | Node::Start | Node::End | Node::WordBoundary(_) => 1,
+        Node::NoWordBefore | Node::NoWordAfter => 1,
+        Node::Concat(nodes) => nodes.iter().map(prog_len).fold(0, u64::saturating_add),
+        // A split and a jump before every branch but the last
+        Node::Alternate(branches) => {
+            branches.iter().map(prog_len).fold(2 * (branches.len() as u64 - 1), u64::saturating_add)
+        }
+        Node::Repeat { node, min, max, .. } => {
+            let len = prog_len(node);
+            let optional = match max {
+                None => len.saturating_add(2),
+                Some(max) => u64::from(max - min).saturating_mul(len + 1),
+            };
+            u64::from(*min).saturating_mul(len).saturating_add(optional)
+        }
+    }
+}
+
+fn split_for(greedy: bool, body: usize, skip: usize) -> Inst {
+    if greedy {
+        Inst::Split(body, skip)
+    } else {
+        Inst::Split(skip, body)
+    }
+}
+
+// Ordered set of threads: insertion order is the priority order of the threads.
+struct Threads {
+    dense: Vec<(usize, usize)>, // (pc, start of the match)
+    sparse: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads { dense: Vec::with_capacity(size), sparse: vec![0; size] }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        i < self.dense.len() && self.dense[i].0 == pc
+    }
+
+    fn insert(&mut self, pc: usize, start: usize) {
+        self.sparse[pc] = self.dense.len();
+        self.dense.push((pc, start));
+    }
+}
+
+/// A compiled regular expression, matched against one line at a time.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    ignore_case: bool,
+}
+
+impl Regex {
+    /// Compiles `pattern`; with `ignore_case` letters match regardless of case.
+    pub fn build(pattern: &str, ignore_case: bool) -> Result<Regex, RegexError> {
+        Regex::compile(Parser::parse(pattern)?, ignore_case)
+    }
+
+    /// Like `build`, but only matches whole words, the way `-w` wants them: with
+    /// no word character right before or right after. Since the check is part
+    /// of the match, `ab|abc` still finds `abc` on its own.
+    pub fn build_words(pattern: &str, ignore_case: bool) -> Result<Regex, RegexError> {
+        let node = Parser::parse(pattern)?;
+        Regex::compile(Node::Concat(vec![Node::NoWordBefore, node, Node::NoWordAfter]), ignore_case)
+    }
+
+    fn compile(node: Node, ignore_case: bool) -> Result<Regex, RegexError> {
+        if prog_len(&node) >= MAX_PROG_LEN {
+            return Err(RegexError::TooBig);
+        }
+        let mut prog = Vec::new();
+        compile(&node, &mut prog);
+        prog.push(Inst::Match);
+        Ok(Regex { prog, ignore_case })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    /// Returns the byte range of the leftmost match in `text`.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Like `find`, but only considers matches starting at or after byte `start`.
+    /// Anchors still refer to the whole of `text`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.prog.len());
+        let mut next = Threads::new(self.prog.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            if matched.is_none() {
+                self.add_thread(&mut current, 0, pos, text_at(text, pos));
+            }
+            if current.dense.is_empty() {
+                break;
+            }
+            let c = text[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+
+            for i in 0..current.dense.len() {
+                let (pc, thread_start) = current.dense[i];
+                let step = match (&self.prog[pc], c) {
+                    (Inst::Match, _) => {
+                        // Lower-priority threads can't win any more
+                        matched = Some((thread_start, pos));
+                        break;
+                    }
+                    (Inst::Char(expected), Some(c)) => self.char_eq(*expected, c),
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(class), Some(c)) => {
+                        if self.ignore_case {
+                            class.matches_ignore_case(c)
+                        } else {
+                            class.matches(c)
+                        }
+                    }
+                    _ => false,
+                };
+                if step {
+                    self.add_thread(&mut next, pc + 1, thread_start, text_at(text, next_pos));
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.dense.clear();
+            pos = next_pos;
+        }
+        matched
+    }
+
+    fn char_eq(&self, expected: char, c: char) -> bool {
+        expected == c || (self.ignore_case && casefold::fold(expected) == casefold::fold(c))
+    }
+
+    // Follows jumps, splits and anchors, adding every reachable instruction
+    // that consumes input (or matches) to `threads`, in priority order.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, start: usize, at: Position) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if threads.contains(pc) {
+                continue;
+            }
+            threads.insert(pc, start);
+            match self.prog[pc] {
+                Inst::Jmp(target) => stack.push(target),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::Start if at.is_start => stack.push(pc + 1),
+                Inst::End if at.is_end => stack.push(pc + 1),
+                Inst::WordBoundary(negated) if at.is_word_boundary() != negated => stack.push(pc + 1),
+                Inst::NoWordBefore if !at.before.is_some_and(is_word_char) => stack.push(pc + 1),
+                Inst::NoWordAfter if !at.after.is_some_and(is_word_char) => stack.push(pc + 1),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Position {
+    is_start: bool,
+    is_end: bool,
+    before: Option<char>,
+    after: Option<char>,
+}
+
+impl Position {
+    // Between a `\w` char and anything else, or the start or end of the text;
+    // the same word chars as `-w`
+    fn is_word_boundary(&self) -> bool {
+        self.before.is_some_and(is_word_char) != self.after.is_some_and(is_word_char)
+    }
+}
+
+fn text_at(text: &str, pos: usize) -> Position {
+    Position {
+        is_start: pos == 0,
+        is_end: pos == text.len(),
+        before: text[..pos].chars().next_back(),
+        after: text[pos..].chars().next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::build(pattern, false).unwrap().find(text)
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(Some((4, 13)), find(r"ERROR \d{3}", "... ERROR 503 upstream"));
+        assert_eq!(None, find(r"ERROR \d{3}", "ERROR 50 upstream"));
+        assert_eq!(Some((0, 3)), find("[a-c]+", "abcd"));
+        assert_eq!(Some((3, 4)), find("[^a-c]", "abcd"));
+        assert_eq!(Some((1, 2)), find(r"[\d_]", "x_1"));
+        assert_eq!(Some((0, 2)), find("a.", "ab"));
+    }
+
+    #[test]
+    fn anchors_and_alternation() {
+        assert_eq!(Some((0, 3)), find("^foo", "foobar"));
+        assert_eq!(None, find("^bar", "foobar"));
+        assert_eq!(Some((3, 6)), find("bar$", "foobar"));
+        assert_eq!(Some((4, 8)), find("warn|error", "an: warn"));
+        assert_eq!(Some((0, 9)), find("^(ab|cd)+e?$", "abcdabcde"));
+        assert_eq!(None, find("^(ab|cd)+$", "abcdx"));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(Some((0, 3)), find(r"\bfoo\b", "foo bar"));
+        assert_eq!(None, find(r"\bfoo\b", "bfoob"));
+        assert_eq!(Some((5, 8)), find(r"\bfoo\b", "foo_ foo"));
+        assert_eq!(Some((1, 4)), find(r"\Bfoo", "xfoo foo"));
+        assert_eq!(Some((3, 6)), find(r"\bé\w", "...éa"));
+        // A combining accent is part of the word, as it is for `-w`
+        assert_eq!(None, find(r"cafe\b", "cafe\u{301}"));
+        assert_eq!(Some((0, 6)), find(r"\w+", "cafe\u{301}"));
+        // Only the text before `start` decides, not where the search begins
+        assert_eq!(None, Regex::build(r"\bar", false).unwrap().find_at("bar", 1));
+    }
+
+    #[test]
+    fn whole_words() {
+        let words = |pattern: &str, text: &str| Regex::build_words(pattern, false).unwrap().find(text);
+
+        // The first alternative would be cut off, so the second one wins
+        assert_eq!(Some((0, 3)), words("ab|abc", "abc"));
+        assert_eq!(Some((5, 8)), words("ab|abc", "abcd abc"));
+        assert_eq!(Some((2, 6)), words("a+", "b aaaa"));
+        assert_eq!(None, words("a+", "baaaa"));
+        // Unlike `\b`, a non-word char at the edge of the match needs nothing around it
+        assert_eq!(Some((2, 5)), words("-fo", "x -fo"));
+    }
+
+    #[test]
+    fn repetition_is_leftmost_first() {
+        assert_eq!(Some((0, 5)), find("a.*b", "axbxb"));
+        assert_eq!(Some((0, 3)), find("a.*?b", "axbxb"));
+        assert_eq!(Some((0, 3)), find("a{2,3}", "aaaa"));
+        assert_eq!(Some((0, 0)), find("x*", "abc"));
+        assert_eq!(Some((0, 4)), find("a{1,}", "aaaa"));
+        assert_eq!(Some((0, 3)), find("a{,", "a{,"));
+    }
+
+    #[test]
+    fn ignore_case_and_unicode() {
+        let re = Regex::build("straße [a-z]+", true).unwrap();
+        assert_eq!(Some((0, 12)), re.find("STRAßE Haus"));
+        assert_eq!(Some((3, 5)), find(r"\w", "...é"));
+        let re = Regex::build("σοφος", true).unwrap();
+        assert_eq!(Some((0, 10)), re.find("ΣΟΦΟΣ"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(RegexError::UnclosedGroup(0), Regex::build("(ab", false).unwrap_err());
+        assert_eq!(RegexError::UnmatchedParen(2), Regex::build("ab)", false).unwrap_err());
+        assert_eq!(RegexError::UnclosedClass(0), Regex::build("[ab", false).unwrap_err());
+        assert_eq!(RegexError::NothingToRepeat(0), Regex::build("*a", false).unwrap_err());
+        assert_eq!(RegexError::InvalidRange(1), Regex::build("[z-a]", false).unwrap_err());
+        assert_eq!(RegexError::DanglingEscape(1), Regex::build(r"a\", false).unwrap_err());
+        assert_eq!(RegexError::UnknownEscape(2), Regex::build(r"ab\p{L}", false).unwrap_err());
+        assert_eq!(RegexError::UnknownEscape(1), Regex::build(r"[\b]", false).unwrap_err());
+        assert_eq!(RegexError::NothingToRepeat(2), Regex::build(r"\b+", false).unwrap_err());
+        assert_eq!(Some((0, 2)), find(r"\.\$", ".$"));
+    }
+
+    #[test]
+    fn program_size_is_limited() {
+        assert_eq!(RegexError::TooBig, Regex::build("((a{1000}){1000}){1000}", false).unwrap_err());
+        assert_eq!(RegexError::TooBig, Regex::build("(a{1000}){100}", false).unwrap_err());
+        assert!(Regex::build("(a{100}){99}", false).is_ok());
+
+        let nested = format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Regex::build(&nested, false).is_ok());
+        let deeper = format!("({nested})");
+        assert_eq!(RegexError::TooDeep(MAX_DEPTH), Regex::build(&deeper, false).unwrap_err());
+        assert_eq!(RegexError::TooDeep(1 + MAX_DEPTH), Regex::build(&format!("a{}", "*".repeat(100_000)), false).unwrap_err());
+        assert_eq!(RegexError::TooDeep(MAX_DEPTH), Regex::build(&"(".repeat(1_000_000), false).unwrap_err());
+        assert_eq!(Some((0, 3)), find("(a{1,1000}){1,3}?", "aaa"));
+    }
+}
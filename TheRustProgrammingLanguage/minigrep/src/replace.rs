@@ -0,0 +1,106 @@
+// Search and replace: `--replace` shows the selected lines with every
+// occurrence replaced, and `--in-place` writes the files back that way.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::matches::Span;
+
+/// `line` with every span replaced by `with`, and the spans of the
+/// replacements in the new line, for highlighting and `-o`.
+pub fn apply(line: &str, spans: &[Span], with: &str) -> (String, Vec<Span>) {
+    let mut replaced = String::with_capacity(line.len());
+    let mut replaced_spans = Vec::with_capacity(spans.len());
+    let mut last = 0;
+    for &(start, end) in spans {
+        replaced.push_str(&line[last..start]);
+        replaced_spans.push((replaced.len(), replaced.len() + with.len()));
+        replaced.push_str(with);
+        last = end;
+    }
+    replaced.push_str(&line[last..]);
+    (replaced, replaced_spans)
+}
+
+/// Replaces the file at `path` with `contents`. With a non-empty
+/// `backup_suffix`, the original is kept next to it, e.g. `poem.txt.bak`.
+///
+/// The new contents go to a temporary file in the same directory first, which
+/// is then renamed over the original: an interrupted write never leaves a
+/// half-written file behind. A symbolic link stays a link: the file it points
+/// to is the one rewritten, and backed up next to itself.
+pub fn write_back(path: &Path, contents: &[u8], backup_suffix: &str) -> io::Result<()> {
+    let target;
+    let path = if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        target = fs::canonicalize(path)?;
+        &target
+    } else {
+        path
+    };
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let temporary = path.with_file_name(format!(".{}.minigrep-tmp", name.to_string_lossy()));
+    fs::write(&temporary, contents)?;
+    fs::set_permissions(&temporary, fs::metadata(path)?.permissions())?;
+
+    if !backup_suffix.is_empty() {
+        fs::rename(path, backup_path(path, backup_suffix))?;
+    }
+    fs::rename(&temporary, path)
+}
+
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(suffix);
+    PathBuf::from(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::scratch_dir;
+
+    #[test]
+    fn replaced_lines_and_spans() {
+        assert_eq!(
+            ("you be or not you be".to_string(), vec![(0, 3), (14, 17)]),
+            apply("to be or not to be", &[(0, 2), (13, 15)], "you")
+        );
+        // The empty query matches at the start of the line
+        assert_eq!(("> line".to_string(), vec![(0, 2)]), apply("line", &[(0, 0)], "> "));
+        assert_eq!(("σ".to_string(), vec![]), apply("σ", &[], "x"));
+    }
+
+    #[test]
+    fn write_back_with_and_without_backup() {
+        let dir = scratch_dir("replace");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "old\n").unwrap();
+
+        write_back(&path, b"new\n", ".bak").unwrap();
+        assert_eq!("new\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("old\n", fs::read_to_string(dir.join("notes.txt.bak")).unwrap());
+
+        write_back(&path, b"newer\n", "").unwrap();
+        assert_eq!("newer\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("old\n", fs::read_to_string(dir.join("notes.txt.bak")).unwrap());
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count()); // no temporary file left
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_back_through_a_symlink() {
+        let dir = scratch_dir("replace-link");
+        fs::create_dir(dir.join("real")).unwrap();
+        let (target, link) = (dir.join("real/notes.txt"), dir.join("link.txt"));
+        fs::write(&target, "old\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_back(&link, b"new\n", ".bak").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!("new\n", fs::read_to_string(&target).unwrap());
+        assert_eq!("old\n", fs::read_to_string(dir.join("real/notes.txt.bak")).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+// The search engine as a library: a `Matcher` finds the occurrences in a line,
+// a `Searcher` decides which lines are selected, and a `Sink` receives them.
+//
+// Every way minigrep itself matches is a `Matcher` (literal, case-insensitive,
+// regex, several patterns, fuzzy), and so is any closure returning the spans of
+// a line, so other crates can plug in their own.
+
+use std::io::{self, BufRead};
+
+use crate::aho_corasick::AhoCorasick;
+use crate::casefold;
+use crate::config::Config;
+use crate::fuzzy::Fuzzy;
+use crate::matches::{self, Match, Span};
+use crate::regex::Regex;
+use crate::stream;
+
+/// Finds the occurrences of something in one line.
+pub trait Matcher {
+    /// The spans of the occurrences in `line`, from left to right and not
+    /// overlapping. An empty span still counts as an occurrence.
+    fn find_spans(&self, line: &str) -> Vec<Span>;
+
+    fn is_match(&self, line: &str) -> bool {
+        !self.find_spans(line).is_empty()
+    }
+}
+
+impl<F: Fn(&str) -> Vec<Span> + ?Sized> Matcher for F {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        self(line)
+    }
+}
+
+/// The query as written.
+#[derive(Debug, Clone)]
+pub struct Literal {
+    query: String,
+}
+
+impl Literal {
+    pub fn new(query: &str) -> Literal {
+        Literal { query: query.to_string() }
+    }
+}
+
+impl Matcher for Literal {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        matches::literal_spans(&self.query, line)
+    }
+}
+
+/// The query in any case, with Unicode case folding (`ΣΟΦΟΣ` matches `σοφος`).
+#[derive(Debug, Clone)]
+pub struct CaseInsensitive {
+    query_folded: Vec<char>,
+}
+
+impl CaseInsensitive {
+    pub fn new(query: &str) -> CaseInsensitive {
+        CaseInsensitive { query_folded: casefold::fold_str(query) }
+    }
+}
+
+impl Matcher for CaseInsensitive {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        matches::case_insensitive_spans(&self.query_folded, line)
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        casefold::contains(line, &self.query_folded)
+    }
+}
+
+impl Matcher for Regex {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        matches::regex_spans(self, line)
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        Regex::is_match(self, line)
+    }
+}
+
+impl Matcher for AhoCorasick {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        AhoCorasick::find_spans(self, line)
+    }
+}
+
+/// The closest occurrence within the distance given to `Fuzzy::new`.
+impl Matcher for Fuzzy {
+    fn find_spans(&self, line: &str) -> Vec<Span> {
+        self.find(line).map(|(span, _)| span).into_iter().collect()
+    }
+}
+
+/// Receives the lines a `Searcher` selects.
+pub trait Sink<'a> {
+    /// Called with each selected line, in order; returning `false` ends the search.
+    fn matched(&mut self, found: Match<'a>) -> bool;
+}
+
+impl<'a> Sink<'a> for Vec<Match<'a>> {
+    fn matched(&mut self, found: Match<'a>) -> bool {
+        self.push(found);
+        true
+    }
+}
+
+impl<'a, F: FnMut(Match<'a>) -> bool> Sink<'a> for F {
+    fn matched(&mut self, found: Match<'a>) -> bool {
+        self(found)
+    }
+}
+
+/// Which lines are selected: those with an occurrence, or with `invert_match`
+/// those without, at most `max_count` of them.
+#[derive(Debug, Clone, Default)]
+pub struct Searcher {
+    pub invert_match: bool,
+    pub max_count: Option<usize>,
+}
+
+impl Searcher {
+    /// Hands the selected lines of `contents` to `sink`, and returns how many there were.
+    pub fn search_str<'a>(&self, matcher: &impl Matcher, contents: &'a str, sink: &mut impl Sink<'a>) -> usize {
+        let mut selected = 0;
+        for (i, line) in contents.lines().enumerate() {
+            if self.max_count == Some(selected) {
+                break;
+            }
+            // `lines` hands out sub-slices of `contents`, so the pointers tell where the line starts
+            let byte_offset = line.as_ptr() as usize - contents.as_ptr() as usize;
+            if let Some(found) = self.select(matcher, i + 1, byte_offset, line) {
+                selected += 1;
+                if !sink.matched(found) {
+                    break;
+                }
+            }
+        }
+        selected
+    }
+
+    /// Like `search_str`, reading one line at a time, the way the command line
+    /// does: compressed input is decompressed, UTF-16 converted, and from the
+    /// first line that isn't UTF-8 on, the input is read as Latin-1.
+    pub fn search_reader<R, S>(&self, matcher: &impl Matcher, reader: R, sink: &mut S) -> io::Result<usize>
+    where
+        R: BufRead,
+        S: for<'l> Sink<'l>,
+    {
+        let config = Config { invert_match: self.invert_match, max_count: self.max_count, ..Config::default() };
+        let (_, reader) = crate::decoded(reader)?;
+        stream::search_sink(reader, sink, &config, matcher)
+    }
+
+    /// The selected lines of `contents`.
+    pub fn lines<'a>(&self, matcher: &impl Matcher, contents: &'a str) -> Vec<&'a str> {
+        let mut lines = Vec::new();
+        self.search_str(matcher, contents, &mut |found: Match<'a>| {
+            lines.push(found.line);
+            true
+        });
+        lines
+    }
+
+    fn select<'a>(&self, matcher: &impl Matcher, line_number: usize, byte_offset: usize, line: &'a str) -> Option<Match<'a>> {
+        let spans = matcher.find_spans(line);
+        if spans.is_empty() != self.invert_match {
+            return None;
+        }
+        Some(Match { line_number, byte_offset, line, spans })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POEM: &str = "I'm nobody! Who are you?\nAre you nobody, too?\nThen there's a pair of us - don't tell!\nThey'd banish us, you know.";
+
+    #[test]
+    fn built_in_matchers() {
+        let searcher = Searcher::default();
+
+        assert_eq!(vec!["Are you nobody, too?"], searcher.lines(&Literal::new("Are"), POEM));
+        assert_eq!(vec!["I'm nobody! Who are you?", "Are you nobody, too?"], searcher.lines(&CaseInsensitive::new("ARE"), POEM));
+        assert_eq!(vec!["They'd banish us, you know."], searcher.lines(&Regex::build("us,", false).unwrap(), POEM));
+        assert_eq!(2, searcher.lines(&AhoCorasick::new(&["pair", "banish"][..], false), POEM).len());
+        assert_eq!(vec!["They'd banish us, you know."], searcher.lines(&Fuzzy::new("vanish", 1, false), POEM));
+        assert!(CaseInsensitive::new("ΣΟΦΟΣ").is_match("ὁ σοφος") && !Literal::new("Who").is_match("who"));
+    }
+
+    #[test]
+    fn closures_as_matchers_and_sinks() {
+        let ends_with_question_mark = |line: &str| if line.ends_with('?') { vec![(line.len() - 1, line.len())] } else { vec![] };
+        let mut numbers = Vec::new();
+        let selected = Searcher::default().search_str(&ends_with_question_mark, POEM, &mut |found: Match| {
+            numbers.push(found.line_number);
+            true
+        });
+
+        assert_eq!((2, vec![1, 2]), (selected, numbers));
+    }
+
+    #[test]
+    fn inverted_and_limited() {
+        let searcher = Searcher { invert_match: true, max_count: Some(1) };
+        let mut found = Vec::new();
+        searcher.search_str(&Literal::new("nobody"), POEM, &mut found);
+
+        assert_eq!(
+            vec![Match { line_number: 3, byte_offset: 46, line: "Then there's a pair of us - don't tell!", spans: vec![] }],
+            found
+        );
+    }
+
+    #[test]
+    fn readers_and_early_stops() {
+        let mut lines = Vec::new();
+        let selected = Searcher::default()
+            .search_reader(&Literal::new("us"), POEM.as_bytes(), &mut |found: Match| {
+                lines.push((found.byte_offset, found.line.to_string()));
+                false // only the first one
+            })
+            .unwrap();
+
+        assert_eq!((1, vec![(46, "Then there's a pair of us - don't tell!".to_string())]), (selected, lines));
+        // Read as Latin-1, like the files minigrep searches
+        let mut lines = Vec::new();
+        Searcher::default()
+            .search_reader(&Literal::new("é"), &b"ok\ncaf\xe9\n"[..], &mut |found: Match| {
+                lines.push(found.line.to_string());
+                true
+            })
+            .unwrap();
+        assert_eq!(vec!["café"], lines);
+    }
+}
@@ -0,0 +1,571 @@
+// Line-by-line search over any reader, for files too big to load and for pipes.
+//
+// Only the current line and the `-B` window are kept in memory, and every
+// selected line is written out as soon as it has been read. The same loop
+// serves `Searcher::search_reader`, handing the lines to a `Sink` instead.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+use crate::color::{self, ColorChoice};
+use crate::config::Config;
+use crate::context;
+use crate::encoding;
+use crate::fuzzy;
+use crate::json;
+use crate::matches::Match;
+use crate::replace;
+use crate::searcher::{Matcher, Sink};
+
+// What grep calls the standard input in its output
+pub const STDIN_NAME: &str = "(standard input)";
+
+/// Writes the lines of `reader` selected by `matcher` (and by the options in `config`)
+/// to `out`. `path` names the input (`None` is the standard input) and prefixes every
+/// line with `config.with_filename`. Returns the number of selected lines.
+pub fn search_reader<R, W, M>(reader: R, out: &mut W, config: &Config, path: Option<&str>, matcher: &M) -> io::Result<usize>
+where
+    R: BufRead,
+    W: Write,
+    M: Matcher + ?Sized,
+{
+    search(reader, &mut Printer::new(out, config, path), None, config, matcher)
+}
+
+/// Like `search_reader`, and also writes the whole input to `rewritten`, with
+/// `config.replace` applied to the selected lines, for `--in-place`.
+pub fn rewrite_reader<R, W, M>(
+    reader: R,
+    out: &mut W,
+    rewritten: &mut Vec<u8>,
+    config: &Config,
+    path: Option<&str>,
+    matcher: &M,
+) -> io::Result<usize>
+where
+    R: BufRead,
+    W: Write,
+    M: Matcher + ?Sized,
+{
+    search(reader, &mut Printer::new(out, config, path), Some(rewritten), config, matcher)
+}
+
+/// Hands the lines of `reader` selected by `matcher` to `sink` instead of
+/// printing them, for `Searcher::search_reader`.
+pub fn search_sink<R, M, S>(reader: R, sink: &mut S, config: &Config, matcher: &M) -> io::Result<usize>
+where
+    R: BufRead,
+    M: Matcher + ?Sized,
+    S: for<'l> Sink<'l>,
+{
+    search(reader, &mut ToSink(sink), None, config, matcher)
+}
+
+// Where `search` puts the lines it picks: the selected ones, the context
+// around them, and at the end of the input the number selected
+trait Output {
+    // `latin1` says the line was read as Latin-1. Returns false to end the search.
+    fn selected(&mut self, found: Match, latin1: bool) -> io::Result<bool>;
+
+    fn context(&mut self, _number: usize, _offset: usize, _line: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, _selected: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    // The input is read as Latin-1 from line `number` on
+    fn latin1_from(&mut self, _number: usize) {}
+}
+
+// The lines of context kept before and printed after a selected line
+fn context_lens(config: &Config) -> (usize, usize) {
+    // Only whole selected lines have context around them
+    if config.only_matching || config.json || config.count || config.lists_files() {
+        return (0, 0);
+    }
+    (config.before(), config.after())
+}
+
+fn search<R, O, M>(
+    mut reader: R,
+    output: &mut O,
+    mut rewritten: Option<&mut Vec<u8>>,
+    config: &Config,
+    matcher: &M,
+) -> io::Result<usize>
+where
+    R: BufRead,
+    O: Output,
+    M: Matcher + ?Sized,
+{
+    let list_files = config.lists_files();
+    let (before_len, after_len) = context_lens(config);
+    let mut before: VecDeque<(usize, usize, String)> = VecDeque::with_capacity(before_len);
+    let mut after_left = 0;
+    let mut selected = 0;
+    let mut buf = Vec::new();
+    let mut number = 0;
+    let mut offset = 0;
+    let mut latin1 = false;
+    let limit = config.max_count.unwrap_or(usize::MAX);
+
+    loop {
+        // Past the -m limit, only the context after the last selected line is left to print
+        if selected == limit && after_left == 0 {
+            break;
+        }
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        number += 1;
+        let line_offset = offset;
+        offset += read;
+        let raw = trim_newline(&buf);
+        // From the first line that isn't UTF-8 on, the input is read as Latin-1,
+        // unless it has to be written back
+        let decoded;
+        let line = match std::str::from_utf8(raw) {
+            Ok(line) if !latin1 => line,
+            Err(e) if rewritten.is_some() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {number} is not valid UTF-8: {e}")));
+            }
+            _ => {
+                if !latin1 {
+                    latin1 = true;
+                    output.latin1_from(number);
+                }
+                decoded = encoding::latin1(raw);
+                &decoded
+            }
+        };
+
+        let spans = matcher.find_spans(line);
+        let found = Match { line_number: number, byte_offset: line_offset, line, spans };
+        let is_selected = found.spans.is_empty() == config.invert_match && selected < limit;
+        if let Some(rewritten) = rewritten.as_deref_mut() {
+            match &config.replace {
+                Some(with) if is_selected => rewritten.extend(replace::apply(line, &found.spans, with).0.as_bytes()),
+                _ => rewritten.extend(line.as_bytes()),
+            }
+            rewritten.extend(&buf[line.len()..]);
+        }
+        if is_selected {
+            selected += 1;
+            // One selected line is all it takes to list the file, or to leave it out,
+            // unless the rest of the file has to be rewritten too
+            if list_files && rewritten.is_none() {
+                break;
+            }
+            if config.count || list_files {
+                continue;
+            }
+            for (number, offset, line) in before.drain(..) {
+                output.context(number, offset, &line)?;
+            }
+            after_left = after_len;
+            if !output.selected(found, latin1)? {
+                break;
+            }
+        } else if config.count {
+            continue;
+        } else if after_left > 0 {
+            output.context(number, line_offset, line)?;
+            after_left -= 1;
+        } else if before_len > 0 {
+            if before.len() == before_len {
+                before.pop_front();
+            }
+            before.push_back((number, line_offset, line.to_string()));
+        }
+    }
+
+    // What is left after -m is copied as it is
+    if let Some(rewritten) = rewritten {
+        reader.read_to_end(rewritten)?;
+    }
+
+    output.finish(selected)?;
+    Ok(selected)
+}
+
+// Same line terminators as `str::lines`: "\n" and "\r\n"
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+// The number of bytes `text` took in the input: one per character in Latin-1
+fn input_len(text: &str, latin1: bool) -> usize {
+    if latin1 {
+        text.chars().count()
+    } else {
+        text.len()
+    }
+}
+
+struct Printer<'a, W> {
+    out: &'a mut W,
+    config: &'a Config,
+    path: Option<&'a str>,
+    prefix: Option<&'a str>,
+    with_context: bool,
+    colored: bool,
+    last_printed: usize, // number of the last line written, 0 before the first one
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    fn new(out: &'a mut W, config: &'a Config, path: Option<&'a str>) -> Printer<'a, W> {
+        let (before_len, after_len) = context_lens(config);
+        Printer {
+            out,
+            config,
+            path,
+            prefix: if config.with_filename { path.or(Some(STDIN_NAME)) } else { None },
+            with_context: before_len > 0 || after_len > 0,
+            // `run` has already decided what `auto` means for its output
+            colored: config.color == ColorChoice::Always,
+            last_printed: 0,
+        }
+    }
+
+    fn print_match(&mut self, found: &Match, latin1: bool) -> io::Result<()> {
+        let distance = self.distance(found);
+        // With --replace, the line is shown the way it would be rewritten
+        if let Some(with) = &self.config.replace {
+            let (line, spans) = replace::apply(found.line, &found.spans, with);
+            return self.print_selected(&Match { line: &line, spans, ..*found }, distance, latin1);
+        }
+        self.print_selected(found, distance, latin1)
+    }
+
+    // With --fuzzy, the typos between the occurrence and the closest pattern
+    fn distance(&self, found: &Match) -> Option<usize> {
+        self.config.fuzzy?;
+        let &(start, end) = found.spans.first()?;
+        let occurrence = &found.line[start..end];
+        self.config.patterns.iter().map(|pattern| fuzzy::distance(pattern, occurrence, self.config.ignore_case)).min()
+    }
+
+    // Offsets count the bytes of the input, like the offset of the line itself
+    fn print_selected(&mut self, found: &Match, distance: Option<usize>, latin1: bool) -> io::Result<()> {
+        let input_offset = |pos: usize| input_len(&found.line[..pos], latin1);
+        if self.config.json {
+            let spans = found.spans.iter().map(|&(start, end)| (input_offset(start), input_offset(end))).collect();
+            return json::write_match(self.out, self.path, &Match { spans, ..*found }, distance);
+        }
+        if !self.config.only_matching {
+            self.print_prefix(found.line_number, found.byte_offset, ':')?;
+            self.print_distance(distance)?;
+            // Highlight every occurrence, leaving the text between them alone
+            let mut last = 0;
+            for &(start, end) in &found.spans {
+                write!(self.out, "{}", &found.line[last..start])?;
+                color::paint(self.out, self.colored, color::MATCH, &found.line[start..end])?;
+                last = end;
+            }
+            return writeln!(self.out, "{}", &found.line[last..]);
+        }
+        // -v has no matched parts to print
+        if self.config.invert_match {
+            return Ok(());
+        }
+        for &(start, end) in found.spans.iter().filter(|(start, end)| end > start) {
+            self.print_prefix(found.line_number, found.byte_offset + input_offset(start), ':')?;
+            self.print_distance(distance)?;
+            color::paint(self.out, self.colored, color::MATCH, &found.line[start..end])?;
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    fn print_distance(&mut self, distance: Option<usize>) -> io::Result<()> {
+        if let Some(distance) = distance {
+            color::paint(self.out, self.colored, color::NUMBER, distance)?;
+            color::paint(self.out, self.colored, color::SEPARATOR, ':')?;
+        }
+        Ok(())
+    }
+
+    fn print_context(&mut self, number: usize, offset: usize, line: &str) -> io::Result<()> {
+        self.print_prefix(number, offset, '-')?;
+        writeln!(self.out, "{line}")
+    }
+
+    // grep's convention: `:` after the path, number and offset of a selected line, `-` for context.
+    // A `number` of 0 stands for the whole input and only gets the path.
+    fn print_prefix(&mut self, number: usize, offset: usize, mark: char) -> io::Result<()> {
+        if self.with_context && self.last_printed > 0 && number > self.last_printed + 1 {
+            color::paint(self.out, self.colored, color::SEPARATOR, context::SEPARATOR)?;
+            writeln!(self.out)?;
+        }
+        self.last_printed = number;
+
+        if let Some(prefix) = self.prefix {
+            color::paint(self.out, self.colored, color::PATH, prefix)?;
+            // With -Z, NUL instead, which no file name can contain
+            if self.config.null {
+                write!(self.out, "\0")?;
+            } else {
+                color::paint(self.out, self.colored, color::SEPARATOR, mark)?;
+            }
+        }
+        if number == 0 {
+            return Ok(());
+        }
+        if self.config.line_number {
+            color::paint(self.out, self.colored, color::NUMBER, number)?;
+            color::paint(self.out, self.colored, color::SEPARATOR, mark)?;
+        }
+        if self.config.byte_offset {
+            color::paint(self.out, self.colored, color::NUMBER, offset)?;
+            color::paint(self.out, self.colored, color::SEPARATOR, mark)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Output for Printer<'_, W> {
+    fn selected(&mut self, found: Match, latin1: bool) -> io::Result<bool> {
+        self.print_match(&found, latin1)?;
+        Ok(true)
+    }
+
+    fn context(&mut self, number: usize, offset: usize, line: &str) -> io::Result<()> {
+        self.print_context(number, offset, line)
+    }
+
+    // Noted when it happens, since the guess made from the start of the input may be UTF-8
+    fn latin1_from(&mut self, number: usize) {
+        eprintln!("minigrep: {}: searched as Latin-1 from line {number}", self.path.unwrap_or(STDIN_NAME));
+    }
+
+    fn finish(&mut self, selected: usize) -> io::Result<()> {
+        if self.config.lists_files() {
+            // -L lists the files where nothing was selected
+            if (selected > 0) != self.config.files_without_match {
+                color::paint(self.out, self.colored, color::PATH, self.path.unwrap_or(STDIN_NAME))?;
+                // NUL-terminated with -Z, so any file name survives `xargs -0`
+                if self.config.null {
+                    write!(self.out, "\0")?;
+                } else {
+                    writeln!(self.out)?;
+                }
+            }
+        } else if self.config.count && self.config.json {
+            json::write_count(self.out, self.path, selected)?;
+        } else if self.config.count {
+            self.print_prefix(0, 0, ':')?;
+            writeln!(self.out, "{selected}")?;
+        }
+        Ok(())
+    }
+}
+
+struct ToSink<'a, S>(&'a mut S);
+
+impl<S: for<'l> Sink<'l>> Output for ToSink<'_, S> {
+    fn selected(&mut self, found: Match, _latin1: bool) -> io::Result<bool> {
+        Ok(self.0.matched(found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matches::literal_spans;
+    use std::io::Cursor;
+
+    const POEM: &str = include_str!("../poem.txt");
+
+    fn output(input: &str, config: &Config, path: Option<&str>, query: &str) -> (String, usize) {
+        let mut out = Vec::new();
+        let find = |line: &str| literal_spans(query, line);
+        let selected = search_reader(Cursor::new(input), &mut out, config, path, &find).unwrap();
+        (String::from_utf8(out).unwrap(), selected)
+    }
+
+    #[test]
+    fn selected_lines_with_numbers() {
+        let config = Config { line_number: true, ..Config::default() };
+
+        assert_eq!(
+            ("6:How dreary to be somebody!\n7:How public, like a frog\n".to_string(), 2),
+            output(POEM, &config, None, "How")
+        );
+    }
+
+    #[test]
+    fn context_and_separators() {
+        let config = Config { context: 1, ..Config::default() };
+        let input = "a\nmatch\nb\nc\nd\ne\nmatch\n";
+
+        assert_eq!("a\nmatch\nb\n--\ne\nmatch\n", output(input, &config, None, "match").0);
+    }
+
+    #[test]
+    fn byte_offsets_and_only_matching() {
+        let config = Config { byte_offset: true, ..Config::default() };
+        assert_eq!("0:a to b\n10:to\n", output("a to b\r\nc\nto\n", &config, None, "to").0);
+
+        let config = Config { only_matching: true, byte_offset: true, line_number: true, context: 2, ..Config::default() };
+        assert_eq!("1:2:to\n1:7:to\n3:12:to\n", output("a to b to\nc\nto\n", &config, None, "to").0);
+    }
+
+    #[test]
+    fn colors() {
+        let config = Config {
+            color: ColorChoice::Always,
+            line_number: true,
+            after_context: Some(1),
+            with_filename: true,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            "\x1b[35mp\x1b[0m\x1b[36m:\x1b[0m\x1b[32m1\x1b[0m\x1b[36m:\x1b[0mgo \x1b[1;31mto\x1b[0m\n\
+             \x1b[35mp\x1b[0m\x1b[36m-\x1b[0m\x1b[32m2\x1b[0m\x1b[36m-\x1b[0mnext\n",
+            output("go to\nnext\nlast\n", &config, Some("p"), "to").0
+        );
+        // `auto` is resolved by `run`; left as it is, it means no escapes at all
+        let config = Config { color: ColorChoice::Auto, ..Config::default() };
+        assert_eq!("go to\n", output("go to\nnext\n", &config, None, "to").0);
+    }
+
+    #[test]
+    fn invert_and_count() {
+        let config = Config { invert_match: true, count: true, with_filename: true, ..Config::default() };
+        assert_eq!(("poem.txt:7\n".to_string(), 7), output(POEM, &config, Some("poem.txt"), "How"));
+
+        let config = Config { count: true, ..Config::default() };
+        assert_eq!("2\n", output(POEM, &config, Some("poem.txt"), "How").0);
+    }
+
+    #[test]
+    fn json_lines() {
+        let config = Config { json: true, context: 1, ..Config::default() };
+        assert_eq!(
+            "{\"path\":\"poem.txt\",\"line_number\":6,\"byte_offset\":115,\"text\":\"How dreary to be somebody!\",\"spans\":[{\"start\":0,\"end\":3}]}\n\
+             {\"path\":\"poem.txt\",\"line_number\":7,\"byte_offset\":142,\"text\":\"How public, like a frog\",\"spans\":[{\"start\":0,\"end\":3}]}\n",
+            output(POEM, &config, Some("poem.txt"), "How").0
+        );
+
+        let config = Config { json: true, count: true, ..Config::default() };
+        assert_eq!("{\"path\":null,\"count\":2}\n", output(POEM, &config, None, "How").0);
+    }
+
+    #[test]
+    fn null_separated_file_list() {
+        let config = Config { files_with_matches: true, null: true, line_number: true, ..Config::default() };
+
+        assert_eq!(("poem.txt\0".to_string(), 1), output(POEM, &config, Some("poem.txt"), "How"));
+        assert_eq!("(standard input)\0", output(POEM, &config, None, "How").0);
+        assert_eq!("", output(POEM, &config, Some("poem.txt"), "Why").0);
+
+        // Alone, -Z only takes the place of the separator after the file name
+        let config = Config { null: true, with_filename: true, line_number: true, ..Config::default() };
+        assert_eq!(
+            ("poem.txt\x006:How dreary to be somebody!\npoem.txt\x007:How public, like a frog\n".to_string(), 2),
+            output(POEM, &config, Some("poem.txt"), "How")
+        );
+        let config = Config { null: true, with_filename: true, count: true, ..Config::default() };
+        assert_eq!("poem.txt\x002\n", output(POEM, &config, Some("poem.txt"), "How").0);
+    }
+
+    #[test]
+    fn files_with_and_without_matches() {
+        let config = Config { files_with_matches: true, ..Config::default() };
+        assert_eq!(("poem.txt\n".to_string(), 1), output(POEM, &config, Some("poem.txt"), "How"));
+        assert_eq!("", output(POEM, &config, Some("poem.txt"), "Why").0);
+
+        let config = Config { files_without_match: true, null: true, ..Config::default() };
+        assert_eq!(("poem.txt\0".to_string(), 0), output(POEM, &config, Some("poem.txt"), "Why"));
+        assert_eq!("", output(POEM, &config, Some("poem.txt"), "How").0);
+    }
+
+    #[test]
+    fn max_count() {
+        let config = Config { max_count: Some(1), line_number: true, ..Config::default() };
+        assert_eq!(("6:How dreary to be somebody!\n".to_string(), 1), output(POEM, &config, None, "How"));
+
+        // The context after the last line is still printed, and a match in it is only context
+        let config = Config { max_count: Some(1), after_context: Some(2), line_number: true, ..Config::default() };
+        assert_eq!("1:to\n2-to\n3-x\n", output("to\nto\nx\nto\n", &config, None, "to").0);
+
+        let config = Config { max_count: Some(0), count: true, ..Config::default() };
+        assert_eq!(("0\n".to_string(), 0), output(POEM, &config, None, "How"));
+    }
+
+    #[test]
+    fn replacement_preview() {
+        let config = Config { replace: Some("you".to_string()), line_number: true, ..Config::default() };
+        assert_eq!("1:you be or not you be\n", output("to be or not to be\nbe\n", &config, None, "to").0);
+
+        let config = Config { replace: Some("X".to_string()), only_matching: true, color: ColorChoice::Always, ..Config::default() };
+        assert_eq!("\x1b[1;31mX\x1b[0m\n\x1b[1;31mX\x1b[0m\n", output("a to b to\n", &config, None, "to").0);
+    }
+
+    #[test]
+    fn rewriting() {
+        let rewrite = |config: &Config, input: &str| {
+            let (mut out, mut rewritten) = (Vec::new(), Vec::new());
+            let find = |line: &str| literal_spans("to", line);
+            rewrite_reader(Cursor::new(input), &mut out, &mut rewritten, config, None, &find).unwrap();
+            (String::from_utf8(out).unwrap(), String::from_utf8(rewritten).unwrap())
+        };
+        let input = "to\r\nskip\nto do\nto";
+
+        let config = Config { replace: Some("TO".to_string()), ..Config::default() };
+        assert_eq!(("TO\nTO do\nTO\n".to_string(), "TO\r\nskip\nTO do\nTO".to_string()), rewrite(&config, input));
+        // Only what is selected changes
+        let config = Config { replace: Some("TO".to_string()), max_count: Some(2), files_with_matches: true, ..Config::default() };
+        assert_eq!(("(standard input)\n".to_string(), "TO\r\nskip\nTO do\nto".to_string()), rewrite(&config, input));
+    }
+
+    #[test]
+    fn fuzzy_distances() {
+        let config = Config { fuzzy: Some(1), patterns: vec!["frog".to_string()], line_number: true, ..Config::default() };
+        let mut out = Vec::new();
+        let matcher = fuzzy::Fuzzy::new("frog", 1, false);
+        search_reader(Cursor::new("a frog\na fog\na dog\n"), &mut out, &config, None, &matcher).unwrap();
+
+        assert_eq!("1:0:a frog\n2:1:a fog\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn line_endings() {
+        let config = Config::default();
+
+        assert_eq!("two\nthree\n", output("one\r\ntwo\r\nthree", &config, None, "t").0);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut out = Vec::new();
+        search_reader(&b"ok\ncaf\xe9\n"[..], &mut out, &Config::default(), None, &|_: &str| vec![(0, 0)]).unwrap();
+        assert_eq!("ok\ncafé\n", String::from_utf8(out).unwrap()); // as Latin-1
+        // Once a line isn't UTF-8, the rest are Latin-1 too
+        let mut out = Vec::new();
+        search_reader(&b"caf\xc3\xa9\nna\xefve\ncaf\xc3\xa9\n"[..], &mut out, &Config::default(), None, &|_: &str| vec![(0, 0)]).unwrap();
+        assert_eq!("café\nnaïve\ncafÃ©\n", String::from_utf8(out).unwrap());
+
+        // Offsets count the bytes of the input, where 'é' is one
+        let config = Config { only_matching: true, byte_offset: true, ..Config::default() };
+        let mut out = Vec::new();
+        search_reader(&b"ok\ncaf\xe9 au lait\n"[..], &mut out, &config, None, &|line: &str| literal_spans("lait", line)).unwrap();
+        assert_eq!("11:lait\n", String::from_utf8(out).unwrap());
+        let config = Config { json: true, ..Config::default() };
+        let mut out = Vec::new();
+        search_reader(&b"caf\xe9 au lait\n"[..], &mut out, &config, None, &|line: &str| literal_spans("lait", line)).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with("\"spans\":[{\"start\":8,\"end\":12}]}\n"));
+
+        // Written back, the line would change its encoding
+        let (mut out, mut rewritten) = (Vec::new(), Vec::new());
+        let config = Config { replace: Some("x".to_string()), ..Config::default() };
+        let error = rewrite_reader(&b"ok\n\xff\n"[..], &mut out, &mut rewritten, &config, None, &|_: &str| vec![(0, 0)]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
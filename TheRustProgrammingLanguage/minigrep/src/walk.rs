@@ -0,0 +1,224 @@
+// Directory traversal for `-r`.
+//
+// Files are visited depth-first in name order, so the output is the same on every
+// run. Symbolic links below the root are not followed, and neither are hidden
+// files and directories or what `.gitignore` and `.ignore` files leave out
+// (including those above the root, up to the top of its git repository),
+// unless the filter says otherwise.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::glob::Glob;
+use crate::ignore::Ignore;
+
+// Number of leading bytes inspected when deciding whether a file is binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// Include/exclude patterns, matched against file (and directory) names, and
+/// whether hidden and ignored files are visited too.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+}
+
+impl Filter {
+    fn accepts(&self, name: &str, is_dir: bool) -> bool {
+        if self.exclude.iter().any(|glob| glob.matches(name)) {
+            return false;
+        }
+        // --include only restricts files: we still need to descend into every directory
+        is_dir || self.include.is_empty() || self.include.iter().any(|glob| glob.matches(name))
+    }
+}
+
+pub struct Walk {
+    stack: Vec<(PathBuf, Rc<Ignore>)>, // pending paths and the rules of their directory, the next one on top
+    root: Option<PathBuf>,
+    filter: Filter,
+}
+
+impl Walk {
+    pub fn new(root: impl AsRef<Path>, filter: Filter) -> Walk {
+        Walk { stack: Vec::new(), root: Some(root.as_ref().to_path_buf()), filter }
+    }
+
+    fn push_children(&mut self, dir: &Path, ignore: &Rc<Ignore>) -> io::Result<()> {
+        let ignore = if self.filter.no_ignore { Rc::clone(ignore) } else { Ignore::child(ignore, dir) };
+        let mut children = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') && !self.filter.hidden {
+                continue;
+            }
+            let path = entry.path();
+            if !self.filter.no_ignore && ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if self.filter.accepts(&name, file_type.is_dir()) {
+                children.push((path, Rc::clone(&ignore)));
+            }
+        }
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.stack.extend(children.into_iter().rev());
+        Ok(())
+    }
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<PathBuf>;
+
+    // Yields every regular file below the root; an unreadable directory yields
+    // an error, after which the walk goes on with its siblings.
+    fn next(&mut self) -> Option<io::Result<PathBuf>> {
+        if let Some(root) = self.root.take() {
+            // The root was named explicitly, so it is followed even if it's a link
+            match fs::metadata(&root) {
+                Ok(meta) if meta.is_dir() => {
+                    let ignore = if self.filter.no_ignore { Rc::default() } else { Ignore::above(&root) };
+                    if let Err(e) = self.push_children(&root, &ignore) {
+                        return Some(Err(e));
+                    }
+                }
+                Ok(_) => return Some(Ok(root)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        while let Some((path, ignore)) = self.stack.pop() {
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => {
+                    if let Err(e) = self.push_children(&path, &ignore) {
+                        return Some(Err(io::Error::new(e.kind(), format!("{}: {e}", path.display()))));
+                    }
+                }
+                Ok(meta) if meta.is_file() => return Some(Ok(path)),
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+/// Heuristic used by grep and friends: a NUL byte near the start means binary data.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Builds a small tree under the system temp directory; each test uses its own name
+    fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("minigrep-walk-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn relative(root: &Path, filter: Filter) -> Vec<String> {
+        Walk::new(root, filter)
+            .map(|path| path.unwrap().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn walks_in_name_order() {
+        let root = tree("order", &[("b.txt", ""), ("a/z.txt", ""), ("a/y/x.txt", ""), ("c.rs", "")]);
+
+        assert_eq!(vec!["a/y/x.txt", "a/z.txt", "b.txt", "c.rs"], relative(&root, Filter::default()));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let root = tree("filter", &[("src/lib.rs", ""), ("src/notes.txt", ""), ("target/out.rs", "")]);
+        let filter = Filter { include: vec![Glob::new("*.rs")], exclude: vec![Glob::new("target")], ..Filter::default() };
+
+        assert_eq!(vec!["src/lib.rs"], relative(&root, filter));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_and_hidden_files() {
+        let root = tree(
+            "ignore",
+            &[
+                (".gitignore", "target/\n*.log\n!keep.log\n/notes.txt\n"),
+                (".hidden/a.txt", ""),
+                ("src/.ignore", "generated.rs\n"),
+                ("src/generated.rs", ""),
+                ("src/lib.rs", ""),
+                ("src/notes.txt", ""),
+                ("src/debug.log", ""),
+                ("keep.log", ""),
+                ("notes.txt", ""),
+                ("target/debug/out.txt", ""),
+            ],
+        );
+
+        assert_eq!(vec!["keep.log", "src/lib.rs", "src/notes.txt"], relative(&root, Filter::default()));
+        let hidden = Filter { hidden: true, ..Filter::default() };
+        assert_eq!(vec![".gitignore", ".hidden/a.txt", "keep.log", "src/.ignore", "src/lib.rs", "src/notes.txt"], relative(&root, hidden));
+        let no_ignore = Filter { no_ignore: true, ..Filter::default() };
+        assert_eq!(
+            vec!["keep.log", "notes.txt", "src/debug.log", "src/generated.rs", "src/lib.rs", "src/notes.txt", "target/debug/out.txt"],
+            relative(&root, no_ignore)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_above_the_root() {
+        let root = tree(
+            "above",
+            &[
+                (".git/info/exclude", "*.tmp\n"),
+                (".gitignore", "*.log\n/sub/skip.txt\n"),
+                ("sub/a.txt", ""),
+                ("sub/b.log", ""),
+                ("sub/c.tmp", ""),
+                ("sub/skip.txt", ""),
+                ("sub/deeper/skip.txt", ""),
+            ],
+        );
+        let sub = root.join("sub");
+
+        assert_eq!(vec!["a.txt", "deeper/skip.txt"], relative(&sub, Filter::default()));
+        // Only within a git repository
+        fs::remove_dir_all(root.join(".git")).unwrap();
+        assert_eq!(vec!["a.txt", "b.log", "c.tmp", "deeper/skip.txt", "skip.txt"], relative(&sub, Filter::default()));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn single_file_root() {
+        let walk: Vec<_> = Walk::new("poem.txt", Filter::default()).map(Result::unwrap).collect();
+
+        assert_eq!(vec![PathBuf::from("poem.txt")], walk);
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\x00"));
+        assert!(!is_binary("I'm nobody! Who are you?".as_bytes()));
+    }
+}
@@ -0,0 +1,79 @@
+// Header fields, as a request or response carries them.
+//
+// Field names are case-insensitive (`Content-Length` and `content-length` are
+// the same field), but they're kept as they came, in the order they came, so
+// repeated fields like `Set-Cookie` survive.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// The value of the first field called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| field.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// The values of every field called `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether a comma-separated field like `Connection` lists `token`, in any case.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).flat_map(|value| value.split(',')).any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Adds a field, after any others of the same name.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+
+        assert_eq!(Some("text/html"), headers.get("content-type"));
+        assert_eq!(vec!["a=1", "b=2"], headers.get_all("SET-COOKIE").collect::<Vec<_>>());
+        assert!(!headers.contains("Content-Length"));
+        assert!(headers.has_token("set-cookie", "B=2"));
+        // Names are kept as they were written
+        assert_eq!(vec![("Content-Type", "text/html"), ("Set-Cookie", "a=1"), ("set-cookie", "b=2")], headers.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn token_lists() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "Upgrade,  Keep-Alive ");
+        headers.append("Connection", "TE");
+
+        assert!(headers.has_token("connection", "keep-alive"));
+        assert!(headers.has_token("Connection", "te"));
+        assert!(!headers.has_token("Connection", "close"));
+        assert!(!headers.has_token("Connection", "keep"));
+    }
+}
@@ -3,6 +3,18 @@ use std::{
     thread
 }; 
 
+mod headers; 
+mod request; 
+mod response; 
+mod router; 
+mod static_files; 
+
+pub use crate::headers::Headers; 
+pub use crate::request::{ParseError, Request}; 
+pub use crate::response::{Body, Response}; 
+pub use crate::router::{Handler, Params, Router}; 
+pub use crate::static_files::StaticFiles; 
+
 pub struct ThreadPool {
     // threads: Vec<thread::JoinHandle<()>>,
     workers: Vec<Worker>, 
@@ -1,31 +1,77 @@
 use std::{
+    env,
     fs,
-    io::{prelude::*, BufReader},
+    io::{self, BufReader, Read, Write},
+    mem,
     net::{TcpListener, TcpStream},
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use web_server_project::ThreadPool; 
+use web_server_project::{Body, Request, Response, Router, StaticFiles, ThreadPool}; 
+
+// How long a client has to send the head of a request, counted from when the
+// connection opens or the previous response went out, and then its body
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5); 
+const BODY_TIMEOUT: Duration = Duration::from_secs(30); 
 
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:7878").unwrap(); 
     let pool = ThreadPool::new(4); 
+    let router = Arc::new(routes()); 
 
-    for stream in listener.incoming().take(2) {
+    // for stream in listener.incoming().take(2) {
+    for stream in listener.incoming() {
         let stream = stream.unwrap(); 
+        let router = Arc::clone(&router); 
 
         // println!("Connection established!"); 
         // handle_connection(stream);
 
-        pool.execute(|| {
-            handle_connection(stream); 
+        pool.execute(move || {
+            handle_connection(stream, &router); 
         }) 
     }
     println!("Shutting down.")
 }
 
-fn handle_connection(mut stream: TcpStream) {
+// Every page the server knows about
+fn routes() -> Router {
+    let mut router = Router::new(); 
+    router.add("GET", "/", |_, _| page(200, "hello.html")); 
+    router.add("GET", "/sleep", |_, _| {
+        thread::sleep(Duration::from_secs(5)); 
+        page(200, "hello.html")
+    }); 
+    // Everything under WEB_ROOT (./public unless it's set), listed if there's no index.html
+    let mut files = StaticFiles::new(env::var_os("WEB_ROOT").unwrap_or_else(|| "public".into())); 
+    files.listings = true; 
+    router.add("GET", "/files/*path", move |request, params| files.serve(request, params.get("path").unwrap_or(""))); 
+    // A long report, sent while another thread is still writing it
+    router.add("GET", "/report", |_, _| {
+        let (reader, mut writer) = io::pipe().unwrap(); 
+        thread::spawn(move || {
+            for row in 1..=10_000 {
+                // An error means the client is gone
+                if writeln!(writer, "row {row}").is_err() {
+                    break; 
+                }
+            }
+        }); 
+        Response::chunked(200, "text/plain; charset=utf-8", reader)
+    }); 
+    router.not_found(|_, _| page(404, "404.html")); 
+    router
+}
+
+fn page(status: u16, filename: &str) -> Response {
+    let contents = fs::read_to_string(filename).unwrap(); 
+    Response::html(status, &contents)
+}
+
+// fn handle_connection(mut stream: TcpStream) {
+fn handle_connection(stream: TcpStream, router: &Router) {
     // let buf_reader = BufReader::new(&mut stream); 
     // let http_request: Vec<_> = buf_reader
     //     .lines()
@@ -46,12 +92,62 @@ fn handle_connection(mut stream: TcpStream) {
     //     format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}"); 
     // stream.write_all(response.as_bytes()).unwrap(); 
 
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader
-        .lines()
-        .next()
-        .unwrap()
-        .unwrap(); 
+    // let buf_reader = BufReader::new(&mut stream);
+    // let request_line = buf_reader
+    //     .lines()
+    //     .next()
+    //     .unwrap()
+    //     .unwrap(); 
+
+    // The same reader for every request, since it may hold the start of the next one already
+    let mut reader = BufReader::new(Deadline { stream: &stream, at: Instant::now() }); 
+    let mut writer = &stream; 
+
+    // Pipelined requests are answered one after the other, in order. A client
+    // that takes too long over a request gets its connection closed.
+    loop {
+        reader.get_mut().at = Instant::now() + HEAD_TIMEOUT; 
+        let request = match Request::read_head(&mut reader) {
+            Ok(Some(mut request)) => {
+                reader.get_mut().at = Instant::now() + BODY_TIMEOUT; 
+                request.read_body(&mut reader).map(|()| request)
+            }
+            // The client closed the connection
+            Ok(None) => return, 
+            Err(e) => Err(e), 
+        }; 
+        let request = match request {
+            Ok(request) => request, 
+            Err(e) => {
+                if let Some(response) = e.response() {
+                    let _ = response.write_to(&mut writer); 
+                }
+                return; 
+            }
+        }; 
+
+        let mut response = router.handle(&request); 
+        // HTTP/1.0 knows nothing of chunks, so a chunked body has to be read to learn its length
+        if request.version == "HTTP/1.0" && response.body.length().is_none() {
+            match mem::replace(&mut response.body, Body::Bytes(Vec::new())).into_bytes() {
+                Ok(bytes) => response.body = Body::Bytes(bytes), 
+                Err(_) => return, 
+            }
+        }
+        // A handler may close the connection too, by saying so in its response
+        let closing = response.headers.has_token("Connection", "close"); 
+        let keep_alive = request.keep_alive() && !closing; 
+        if !keep_alive && !closing {
+            response.headers.append("Connection", "close"); 
+        } else if keep_alive && request.version == "HTTP/1.0" {
+            // HTTP/1.0 clients assume the connection closes unless told otherwise
+            response.headers.append("Connection", "keep-alive"); 
+        }
+        // The client may be gone already; there's no one left to tell
+        if response.write_to(&mut writer).is_err() || !keep_alive {
+            return; 
+        }
+    }
     
     // if request_line == "GET / HTTP/1.1" {
     //     let status_line = "HTTP:/1.1 200 OK"; 
@@ -78,21 +174,39 @@ fn handle_connection(mut stream: TcpStream) {
     //     ("HTTP/1.1 404 NOT FOUND", "404.html")
     // };
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"), 
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5)); 
-            ("HTTP/1.1 200 OK", "hello.html")
-        }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html")
-    }; 
+    // let (status_line, filename) = match &request_line[..] {
+    //     "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"), 
+    //     "GET /sleep HTTP/1.1" => {
+    //         thread::sleep(Duration::from_secs(5)); 
+    //         ("HTTP/1.1 200 OK", "hello.html")
+    //     }
+    //     _ => ("HTTP/1.1 404 NOT FOUND", "404.html")
+    // }; 
+
+    // let contents = fs::read_to_string(filename).unwrap();
+    // let length = contents.len();
+    // let response =
+    //     format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+    // stream.write_all(response.as_bytes()).unwrap();
+
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response =
-        format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    stream.write_all(response.as_bytes()).unwrap();
 
+}
 
+// Reads from a connection until a deadline, however slowly the bytes trickle
+// in: a read timeout alone starts over with each one that arrives
+struct Deadline<'a> {
+    stream: &'a TcpStream, 
+    at: Instant, 
+}
 
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.at.saturating_duration_since(Instant::now()); 
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the client took too long")); 
+        }
+        self.stream.set_read_timeout(Some(left))?; 
+        self.stream.read(buf)
+    }
 }
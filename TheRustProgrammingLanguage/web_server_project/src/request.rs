@@ -0,0 +1,456 @@
+// HTTP/1.1 requests, read off a connection: the request line, the header
+// fields and a body of `Content-Length` bytes, or in chunks (RFC 9112).
+//
+// Lines end with CRLF, but a bare LF is accepted too, as the RFC allows. The
+// header section and the body have size limits, so that a client can't make
+// the server buffer whatever it sends.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use crate::headers::Headers;
+use crate::response::Response;
+
+// Limits on what a request may take up
+const MAX_HEAD_LEN: usize = 8 * 1024;
+const MAX_BODY_LEN: usize = 1024 * 1024;
+const MAX_CHUNK_LINE_LEN: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub target: String,  // as sent, e.g. `/search?q=rust`
+    pub version: String, // `HTTP/1.1` or `HTTP/1.0`
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    InvalidRequestLine(String),
+    UnsupportedVersion(String),
+    InvalidHeader(String),
+    MissingHost,
+    InvalidContentLength(String),
+    UnsupportedTransferEncoding(String),
+    // Both Content-Length and Transfer-Encoding, so the body could end in two places
+    AmbiguousLength,
+    InvalidChunk,
+    HeadTooLarge,
+    BodyTooLarge(usize),
+    // The connection ended in the middle of the request, or failed
+    Incomplete,
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidRequestLine(line) => write!(f, "invalid request line '{line}'"),
+            ParseError::UnsupportedVersion(version) => write!(f, "unsupported HTTP version '{version}'"),
+            ParseError::InvalidHeader(line) => write!(f, "invalid header field '{line}'"),
+            ParseError::MissingHost => write!(f, "an HTTP/1.1 request needs a Host header"),
+            ParseError::InvalidContentLength(value) => write!(f, "invalid Content-Length '{value}'"),
+            ParseError::UnsupportedTransferEncoding(coding) => write!(f, "unsupported Transfer-Encoding '{coding}'"),
+            ParseError::AmbiguousLength => write!(f, "a request can't have both Content-Length and Transfer-Encoding"),
+            ParseError::InvalidChunk => write!(f, "invalid chunk in a chunked body"),
+            ParseError::HeadTooLarge => write!(f, "the request line and headers take more than {MAX_HEAD_LEN} bytes"),
+            ParseError::BodyTooLarge(length) => write!(f, "a body of {length} bytes is more than the {MAX_BODY_LEN} allowed"),
+            ParseError::Incomplete => write!(f, "the request ended too early"),
+            ParseError::Io(kind) => write!(f, "reading the request failed: {kind}"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e.kind())
+    }
+}
+
+impl ParseError {
+    /// The status to answer with, or `None` when the connection is gone.
+    pub fn status(&self) -> Option<u16> {
+        Some(match self {
+            ParseError::Io(_) => return None,
+            ParseError::UnsupportedVersion(_) => 505,
+            ParseError::UnsupportedTransferEncoding(_) => 501,
+            ParseError::HeadTooLarge => 431,
+            ParseError::BodyTooLarge(_) => 413,
+            _ => 400,
+        })
+    }
+
+    /// The response explaining what was wrong. The connection should be closed
+    /// after it, since where the next request starts is anyone's guess.
+    pub fn response(&self) -> Option<Response> {
+        let mut response = Response::text(self.status()?, &format!("{self}\n"));
+        response.headers.append("Connection", "close");
+        Some(response)
+    }
+}
+
+impl Request {
+    /// Reads the next request from `reader`. Returns `None` when the input ends
+    /// before a request starts, as it does when a client closes the connection.
+    pub fn read_from(reader: &mut impl BufRead) -> Result<Option<Request>, ParseError> {
+        let Some(mut request) = Request::read_head(reader)? else {
+            return Ok(None);
+        };
+        request.read_body(reader)?;
+        Ok(Some(request))
+    }
+
+    /// Like `read_from`, but stops after the header fields, leaving the body
+    /// to `read_body`.
+    pub fn read_head(reader: &mut impl BufRead) -> Result<Option<Request>, ParseError> {
+        let mut budget = MAX_HEAD_LEN;
+        // Empty lines before a request are to be ignored
+        let request_line = loop {
+            match read_line(reader, &mut budget)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let (method, target, version) = parse_request_line(&request_line)?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader, &mut budget)?.ok_or(ParseError::Incomplete)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+        if version == "HTTP/1.1" && !headers.contains("Host") {
+            return Err(ParseError::MissingHost);
+        }
+        let body = Vec::new();
+        Ok(Some(Request { method: method.to_string(), target: target.to_string(), version: version.to_string(), headers, body }))
+    }
+
+    /// Reads the body that follows the head of the request.
+    pub fn read_body(&mut self, reader: &mut impl BufRead) -> Result<(), ParseError> {
+        let headers = &self.headers;
+        self.body = if headers.contains("Transfer-Encoding") {
+            // Which of the two to believe is how requests get smuggled past a proxy
+            if headers.contains("Content-Length") {
+                return Err(ParseError::AmbiguousLength);
+            }
+            // Only `chunked` on its own: anything else would need decompressing
+            let codings: Vec<&str> = headers.get_all("Transfer-Encoding").flat_map(|value| value.split(',')).map(str::trim).collect();
+            if !matches!(codings[..], [coding] if coding.eq_ignore_ascii_case("chunked")) {
+                return Err(ParseError::UnsupportedTransferEncoding(codings.join(", ")));
+            }
+            // The trailer fields get a head's worth of room
+            read_chunked(reader, &mut { MAX_HEAD_LEN })?
+        } else {
+            let length = content_length(headers)?;
+            let mut body = Vec::with_capacity(length);
+            read_exactly(reader, length, &mut body)?;
+            body
+        };
+        Ok(())
+    }
+
+    /// The value of the first header field called `name`, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The target without its query string.
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Whether the client wants the connection kept open after the response.
+    /// HTTP/1.1 keeps it unless told `Connection: close`; HTTP/1.0 closes it
+    /// unless told `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        match &self.version[..] {
+            "HTTP/1.1" => !self.headers.has_token("Connection", "close"),
+            _ => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+}
+
+// One line without its line ending, taken out of `budget`; `None` if the input
+// ended right away
+fn read_line(reader: &mut impl BufRead, budget: &mut usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(*budget as u64 + 1).read_until(b'\n', &mut line)?;
+    if read > *budget {
+        return Err(ParseError::HeadTooLarge);
+    }
+    *budget -= read;
+    match line.strip_suffix(b"\n") {
+        Some(line) => {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            // Header values may hold Latin-1 in theory; in practice, that's a broken client
+            String::from_utf8(line.to_vec())
+                .map(Some)
+                .map_err(|_| ParseError::InvalidHeader(String::from_utf8_lossy(line).into_owned()))
+        }
+        None if read == 0 => Ok(None),
+        None => Err(ParseError::Incomplete),
+    }
+}
+
+// `length` more bytes of the body
+fn read_exactly(reader: &mut impl BufRead, length: usize, body: &mut Vec<u8>) -> Result<(), ParseError> {
+    let read = reader.by_ref().take(length as u64).read_to_end(body)?;
+    if read < length {
+        return Err(ParseError::Incomplete);
+    }
+    Ok(())
+}
+
+// A chunked body: chunks, each after a line with its size in hex, up to one of
+// size 0. The trailer fields after it, taken out of `budget`, are checked but
+// dropped, since they aren't headers and few clients send any.
+fn read_chunked(reader: &mut impl BufRead, budget: &mut usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = chunk_line(reader, MAX_CHUNK_LINE_LEN)?;
+        // Chunk extensions, after a `;`, mean nothing to us
+        let size = line.split(';').next().unwrap_or("").trim_end_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunk)?;
+        if size == 0 {
+            break;
+        }
+        if body.len().saturating_add(size) > MAX_BODY_LEN {
+            return Err(ParseError::BodyTooLarge(body.len().saturating_add(size)));
+        }
+        read_exactly(reader, size, &mut body)?;
+        // Every chunk ends with a line break of its own
+        if !chunk_line(reader, 2)?.is_empty() {
+            return Err(ParseError::InvalidChunk);
+        }
+    }
+
+    loop {
+        let line = read_line(reader, budget)?.ok_or(ParseError::Incomplete)?;
+        if line.is_empty() {
+            return Ok(body);
+        }
+        parse_header(&line)?;
+    }
+}
+
+// A line of a chunked body, which can't take more than `limit` bytes
+fn chunk_line(reader: &mut impl BufRead, limit: usize) -> Result<String, ParseError> {
+    match read_line(reader, &mut { limit }) {
+        Ok(Some(line)) => Ok(line),
+        Ok(None) => Err(ParseError::Incomplete),
+        Err(ParseError::HeadTooLarge) => Err(ParseError::InvalidChunk),
+        Err(e) => Err(e),
+    }
+}
+
+// `GET /index.html HTTP/1.1`: single spaces, and nothing else
+fn parse_request_line(line: &str) -> Result<(&str, &str, &str), ParseError> {
+    let invalid = || ParseError::InvalidRequestLine(line.to_string());
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !is_token(method) || target.is_empty() || target.chars().any(|c| c.is_control()) {
+        return Err(invalid());
+    }
+    match version {
+        "HTTP/1.1" | "HTTP/1.0" => Ok((method, target, version)),
+        _ if version.starts_with("HTTP/") => Err(ParseError::UnsupportedVersion(version.to_string())),
+        _ => Err(invalid()),
+    }
+}
+
+// `Name: value`, where the value loses the spaces around it. A line starting
+// with whitespace would continue the previous one, which RFC 9112 forbids.
+fn parse_header(line: &str) -> Result<(&str, &str), ParseError> {
+    match line.split_once(':') {
+        Some((name, value)) if is_token(name) => Ok((name, value.trim_matches([' ', '\t']))),
+        _ => Err(ParseError::InvalidHeader(line.to_string())),
+    }
+}
+
+// Every Content-Length field has to agree, even within a list like `5, 5`
+fn content_length(headers: &Headers) -> Result<usize, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        let invalid = || ParseError::InvalidContentLength(value.to_string());
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let parsed: usize = value.parse().map_err(|_| invalid())?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(invalid());
+        }
+        length = Some(parsed);
+    }
+    match length {
+        Some(length) if length > MAX_BODY_LEN => Err(ParseError::BodyTooLarge(length)),
+        length => Ok(length.unwrap_or(0)),
+    }
+}
+
+// The characters allowed in methods and field names
+fn is_token(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Result<Option<Request>, ParseError> {
+        Request::read_from(&mut &bytes[..])
+    }
+
+    #[test]
+    fn get_request() {
+        let request = parse(b"GET /search?q=rust HTTP/1.1\r\nHost: localhost:7878\r\nUser-Agent: curl/8.5.0\r\nAccept: */*\r\n\r\n")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(("GET", "/search?q=rust", "HTTP/1.1"), (&request.method[..], &request.target[..], &request.version[..]));
+        assert_eq!(("/search", Some("q=rust")), (request.path(), request.query()));
+        assert_eq!(Some("curl/8.5.0"), request.header("user-agent"));
+        assert_eq!(Some("localhost:7878"), request.header("HOST"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn body_and_lenient_line_endings() {
+        let mut input = &b"\r\nPOST /notes HTTP/1.1\nHost: x\nContent-Length:  5 \n\nhello and the next request"[..];
+        let request = Request::read_from(&mut input).unwrap().unwrap();
+
+        assert_eq!(("POST", b"hello".to_vec()), (&request.method[..], request.body));
+        assert_eq!(b" and the next request", input); // nothing past the body was read
+        assert_eq!(Ok(None), parse(b""));
+        assert_eq!(Ok(None), parse(b"\r\n\r\n"));
+    }
+
+    #[test]
+    fn head_then_body() {
+        let mut input = &b"POST /notes HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n"[..];
+        let mut request = Request::read_head(&mut input).unwrap().unwrap();
+
+        assert!(request.body.is_empty());
+        assert_eq!(b"2\r\nok\r\n0\r\n\r\n", input);
+        request.read_body(&mut input).unwrap();
+        assert_eq!((b"ok".to_vec(), &b""[..]), (request.body, input));
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let mut input = &b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nokGET /c HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n"[..];
+        let mut next = || Request::read_from(&mut input).unwrap().map(|request| (request.path().to_string(), request.keep_alive()));
+
+        assert_eq!(Some(("/a".to_string(), true)), next());
+        assert_eq!(Some(("/b".to_string(), true)), next());
+        assert_eq!(Some(("/c".to_string(), false)), next());
+        assert_eq!(None, next());
+    }
+
+    #[test]
+    fn keep_alive_by_version() {
+        let keep_alive = |bytes: &[u8]| parse(bytes).unwrap().unwrap().keep_alive();
+
+        assert!(keep_alive(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"));
+    }
+
+    #[test]
+    fn http_1_0_needs_no_host() {
+        assert_eq!("HTTP/1.0", parse(b"GET / HTTP/1.0\r\n\r\n").unwrap().unwrap().version);
+        assert_eq!(Err(ParseError::MissingHost), parse(b"GET / HTTP/1.1\r\n\r\n"));
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let invalid_line = |line: &str| Err(ParseError::InvalidRequestLine(line.to_string()));
+
+        assert_eq!(invalid_line("GET /"), parse(b"GET /\r\n\r\n"));
+        assert_eq!(invalid_line("GET  / HTTP/1.1"), parse(b"GET  / HTTP/1.1\r\n\r\n"));
+        assert_eq!(invalid_line("G(E)T / HTTP/1.1"), parse(b"G(E)T / HTTP/1.1\r\n\r\n"));
+        assert_eq!(invalid_line("GET / FTP/1.1"), parse(b"GET / FTP/1.1\r\n\r\n"));
+        assert_eq!(Err(ParseError::UnsupportedVersion("HTTP/2.0".to_string())), parse(b"GET / HTTP/2.0\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidHeader("Host : x".to_string())), parse(b"GET / HTTP/1.1\r\nHost : x\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidHeader(" folded".to_string())), parse(b"GET / HTTP/1.1\r\nHost: x\r\n folded\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidHeader("X: \u{fffd}".to_string())), parse(b"GET / HTTP/1.1\r\nX: \xff\r\n\r\n"));
+        assert_eq!(Err(ParseError::Incomplete), parse(b"GET / HTTP/1.1\r\nHost: x\r\n"));
+        assert_eq!(Err(ParseError::Incomplete), parse(b"GET / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nshort"));
+    }
+
+    #[test]
+    fn content_lengths() {
+        let with_length = |value: &str| parse(format!("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: {value}\r\n\r\nabc").as_bytes());
+
+        assert_eq!(b"abc".to_vec(), with_length("3, 3").unwrap().unwrap().body);
+        assert_eq!(Err(ParseError::InvalidContentLength("+3".to_string())), with_length("+3"));
+        assert_eq!(Err(ParseError::InvalidContentLength("2".to_string())), with_length("3, 2"));
+        assert_eq!(Err(ParseError::InvalidContentLength("99999999999999999999999".to_string())), with_length("99999999999999999999999"));
+        assert_eq!(Err(ParseError::BodyTooLarge(5_000_000)), with_length("5000000"));
+        assert_eq!(
+            Err(ParseError::UnsupportedTransferEncoding("gzip".to_string())),
+            parse(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n")
+        );
+    }
+
+    #[test]
+    fn chunked_bodies() {
+        let chunked = |encoding: &str, body: &str| {
+            let request = format!("POST /report HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: {encoding}\r\n\r\n{body}");
+            Request::read_from(&mut request.as_bytes()).map(|request| request.unwrap().body)
+        };
+
+        assert_eq!(Ok(b"Wikipedia in\r\n\r\nchunks.".to_vec()), chunked("chunked", "4\r\nWiki\r\n5;note=x\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\n\r\n"));
+        assert_eq!(Ok(b"abc".to_vec()), chunked("Chunked", "3\nabc\n0\nExpires: never\n\n"));
+        assert_eq!(Ok(Vec::new()), chunked("chunked", "0\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidChunk), chunked("chunked", "+3\r\nabc\r\n0\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidChunk), chunked("chunked", "2\r\nabc\r\n0\r\n\r\n"));
+        assert_eq!(Err(ParseError::InvalidChunk), chunked("chunked", "fffffffffffffffffffff\r\n"));
+        assert_eq!(Err(ParseError::BodyTooLarge(0x200000)), chunked("chunked", "200000\r\n"));
+        assert_eq!(Err(ParseError::Incomplete), chunked("chunked", "3\r\nabc\r\n"));
+        assert_eq!(Err(ParseError::UnsupportedTransferEncoding("gzip, chunked".to_string())), chunked("gzip, chunked", "0\r\n\r\n"));
+        assert_eq!(
+            Err(ParseError::AmbiguousLength),
+            parse(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n")
+        );
+    }
+
+    #[test]
+    fn size_limits() {
+        let long_header = format!("GET / HTTP/1.1\r\nHost: x\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_HEAD_LEN));
+
+        assert_eq!(Err(ParseError::HeadTooLarge), parse(long_header.as_bytes()));
+    }
+
+    #[test]
+    fn error_responses() {
+        let mut out = Vec::new();
+        parse(b"GET /\r\n\r\n").unwrap_err().response().unwrap().write_to(&mut out).unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 400 BAD REQUEST\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\nContent-Length: 29\r\n\r\ninvalid request line 'GET /'\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(Some(505), ParseError::UnsupportedVersion("HTTP/3".to_string()).status());
+        assert!(ParseError::Io(io::ErrorKind::ConnectionReset).response().is_none());
+    }
+}
@@ -0,0 +1,258 @@
+// HTTP/1.1 responses: a status, header fields and a body, written out with
+// the `Content-Length` it needs.
+//
+// A body is either in memory or read while it is written, like a file that
+// shouldn't have to fit in memory first. A body whose length isn't known until
+// it ends, like a report that is still being generated, is sent with chunked
+// transfer encoding instead (RFC 9112, section 7.1).
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::headers::Headers;
+
+// The most a chunk of a chunked body holds
+const CHUNK_LEN: usize = 8 * 1024;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    // The first `length` bytes of the reader
+    Reader(Box<dyn Read + Send>, u64),
+    // Everything the reader has, sent in chunks as it comes
+    Chunked(Box<dyn Read + Send>),
+}
+
+impl Body {
+    /// The length in bytes, unless the body is chunked and nobody knows it yet.
+    pub fn length(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader(_, length) => Some(*length),
+            Body::Chunked(_) => None,
+        }
+    }
+
+    /// The whole body in memory.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Reader(reader, length) => {
+                let mut bytes = Vec::new();
+                reader.take(length).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Body::Chunked(mut reader) => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => write!(f, "Bytes({:?})", String::from_utf8_lossy(bytes)),
+            Body::Reader(_, length) => write!(f, "Reader({length} bytes)"),
+            Body::Chunked(_) => write!(f, "Chunked"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    /// A response with no body.
+    pub fn new(status: u16) -> Response {
+        Response { status, headers: Headers::new(), body: Body::Bytes(Vec::new()) }
+    }
+
+    pub fn html(status: u16, contents: &str) -> Response {
+        Response::with_body(status, "text/html; charset=utf-8", contents.as_bytes())
+    }
+
+    pub fn text(status: u16, contents: &str) -> Response {
+        Response::with_body(status, "text/plain; charset=utf-8", contents.as_bytes())
+    }
+
+    /// A response sending everything `reader` produces, chunk by chunk, for a
+    /// body whose length isn't known up front.
+    pub fn chunked(status: u16, content_type: &str, reader: impl Read + Send + 'static) -> Response {
+        let mut response = Response::new(status).with_header("Content-Type", content_type);
+        response.body = Body::Chunked(Box::new(reader));
+        response
+    }
+
+    /// Adds a header field, so that a response can be built in one expression.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    fn with_body(status: u16, content_type: &str, body: &[u8]) -> Response {
+        let mut response = Response::new(status);
+        response.headers.append("Content-Type", content_type);
+        response.body = Body::Bytes(body.to_vec());
+        response
+    }
+
+    /// Like `HTTP/1.1 404 NOT FOUND`.
+    pub fn status_line(&self) -> String {
+        format!("HTTP/1.1 {} {}", self.status, reason(self.status))
+    }
+
+    /// Writes the response to `out`, reading the body if it has to. A 1xx, 204
+    /// or 304 response never has a body, so whatever it was given is dropped.
+    pub fn write_to(self, out: &mut impl Write) -> io::Result<()> {
+        let bodiless = matches!(self.status, 100..=199 | 204 | 304);
+        // A 304 may tell the length the full response would have had; the others may not
+        let framing_allowed = !bodiless || self.status == 304;
+        let mut head = self.status_line() + "\r\n";
+        for (name, value) in self.headers.iter() {
+            let is_framing = name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding");
+            if framing_allowed || !is_framing {
+                head += &format!("{name}: {value}\r\n");
+            }
+        }
+        match self.body.length() {
+            _ if bodiless => {}
+            Some(length) if !self.headers.contains("Content-Length") => head += &format!("Content-Length: {length}\r\n"),
+            None if !self.headers.contains("Transfer-Encoding") => head += "Transfer-Encoding: chunked\r\n",
+            _ => {}
+        }
+        head += "\r\n";
+        out.write_all(head.as_bytes())?;
+        if bodiless {
+            return out.flush();
+        }
+        match self.body {
+            Body::Bytes(bytes) => out.write_all(&bytes)?,
+            Body::Reader(reader, length) => {
+                let copied = io::copy(&mut reader.take(length), out)?;
+                // The length has been promised already, so a short body breaks the connection
+                if copied < length {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the body ended before its length"));
+                }
+            }
+            Body::Chunked(mut reader) => write_chunks(&mut reader, out)?,
+        }
+        out.flush()
+    }
+}
+
+// Each read is a chunk: its size in hex, then its bytes. An empty chunk ends
+// the body, with no trailer fields after it. A reader that fails can't be
+// reported to the client anymore, other than by breaking off the body.
+fn write_chunks(reader: &mut impl Read, out: &mut impl Write) -> io::Result<()> {
+    let mut buffer = vec![0; CHUNK_LEN];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write!(out, "{read:X}\r\n")?;
+        out.write_all(&buffer[..read])?;
+        out.write_all(b"\r\n")?;
+        // The client sees each chunk as soon as it's ready
+        out.flush()?;
+    }
+    out.write_all(b"0\r\n\r\n")
+}
+
+// The reason phrases, written the way the rest of the server writes them
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "CONTINUE",
+        200 => "OK",
+        201 => "CREATED",
+        204 => "NO CONTENT",
+        206 => "PARTIAL CONTENT",
+        301 => "MOVED PERMANENTLY",
+        302 => "FOUND",
+        304 => "NOT MODIFIED",
+        400 => "BAD REQUEST",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        405 => "METHOD NOT ALLOWED",
+        408 => "REQUEST TIMEOUT",
+        411 => "LENGTH REQUIRED",
+        413 => "CONTENT TOO LARGE",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        500 => "INTERNAL SERVER ERROR",
+        501 => "NOT IMPLEMENTED",
+        503 => "SERVICE UNAVAILABLE",
+        505 => "HTTP VERSION NOT SUPPORTED",
+        _ => "UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_bodies() {
+        let mut response = Response::new(200);
+        response.body = Body::Reader(Box::new(&b"\x89PNG\r\n and more"[..]), 6);
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert_eq!(&b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n\x89PNG\r\n"[..], &out[..]);
+
+        response = Response::new(200);
+        response.body = Body::Reader(Box::new(&b"short"[..]), 10);
+        assert_eq!(io::ErrorKind::UnexpectedEof, response.write_to(&mut Vec::new()).unwrap_err().kind());
+    }
+
+    #[test]
+    fn chunked_bodies() {
+        // Each read is a chunk, as a generated report would hand out its rows
+        let rows = (&b"row 1\n"[..]).chain(&b"row 2\n"[..]);
+        let mut out = Vec::new();
+        Response::chunked(200, "text/plain", rows).with_header("Cache-Control", "no-store").write_to(&mut out).unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nCache-Control: no-store\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nrow 1\n\r\n6\r\nrow 2\n\r\n0\r\n\r\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(None, Response::chunked(200, "text/plain", io::empty()).body.length());
+    }
+
+    #[test]
+    fn written_with_length() {
+        let mut response = Response::html(200, "<p>hi</p>");
+        response.headers.append("Cache-Control", "no-store");
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nCache-Control: no-store\r\nContent-Length: 9\r\n\r\n<p>hi</p>",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!("HTTP/1.1 405 METHOD NOT ALLOWED", Response::new(405).status_line());
+    }
+
+    #[test]
+    fn bodiless_statuses() {
+        let written = |response: Response| {
+            let mut out = Vec::new();
+            response.write_to(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!("HTTP/1.1 204 NO CONTENT\r\n\r\n", written(Response::new(204)));
+        assert_eq!("HTTP/1.1 204 NO CONTENT\r\n\r\n", written(Response::new(204).with_header("Content-Length", "0")));
+        assert_eq!("HTTP/1.1 100 CONTINUE\r\nContent-Type: text/plain\r\n\r\n", written(Response::chunked(100, "text/plain", io::empty())));
+        assert_eq!(
+            "HTTP/1.1 304 NOT MODIFIED\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            written(Response::text(304, "unchanged"))
+        );
+    }
+}
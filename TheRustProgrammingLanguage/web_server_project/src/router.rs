@@ -0,0 +1,247 @@
+// Picks the handler for a request by its method and path.
+//
+// A pattern is a path whose segments may be parameters: `/users/:id` matches
+// `/users/42` with `id` = `42`. A last segment `*name` matches whatever is
+// left of the path, slashes included, and a bare `*` does the same without
+// keeping it. Routes are tried in the order they were added. When none has a
+// matching pattern the answer is 404; when some do, but not for the request's
+// method, it's 405 with the methods that would have worked.
+
+use crate::request::Request;
+use crate::response::Response;
+
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(Option<String>),
+}
+
+struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: Handler,
+}
+
+/// The parameters a pattern took from the path, percent-decoded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Option<Handler>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Sends the requests for `method` and paths matching `pattern` to `handler`.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` doesn't start with `/`, or has a `*` segment anywhere but at its end.
+    pub fn add<F>(&mut self, method: &str, pattern: &str, handler: F)
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        let pattern = parse_pattern(pattern);
+        self.routes.push(Route { method: method.to_string(), pattern, handler: Box::new(handler) });
+    }
+
+    /// Answers the requests no route matches, instead of a plain 404.
+    pub fn not_found<F>(&mut self, handler: F)
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Some(Box::new(handler));
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let mut allowed: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            let Some(params) = matches(&route.pattern, request.path()) else {
+                continue;
+            };
+            if route.method == request.method {
+                return (route.handler)(request, &params);
+            }
+            if !allowed.contains(&&route.method[..]) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            return match &self.not_found {
+                Some(handler) => handler(request, &Params::default()),
+                None => Response::text(404, &format!("no route for {}\n", request.path())),
+            };
+        }
+        let mut response = Response::text(405, &format!("{} isn't allowed for {}\n", request.method, request.path()));
+        response.headers.append("Allow", &allowed.join(", "));
+        response
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments = pattern.strip_prefix('/').unwrap_or_else(|| panic!("pattern '{pattern}' doesn't start with '/'"));
+    let segments: Vec<&str> = segments.split('/').collect();
+    let last = segments.len() - 1;
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| match segment.strip_prefix('*') {
+            Some(_) if i != last => panic!("pattern '{pattern}' has a '*' segment before its end"),
+            Some("") => Segment::Rest(None),
+            Some(name) => Segment::Rest(Some(name.to_string())),
+            None => match segment.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(segment.to_string()),
+            },
+        })
+        .collect()
+}
+
+// The parameters, if `path` matches `pattern`
+fn matches(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut params = Params::default();
+    let mut rest = path.strip_prefix('/')?;
+    for (i, segment) in pattern.iter().enumerate() {
+        if let Segment::Rest(name) = segment {
+            if let Some(name) = name {
+                params.values.push((name.clone(), percent_decode(rest)));
+            }
+            return Some(params);
+        }
+        let (part, after) = rest.split_once('/').unwrap_or((rest, ""));
+        let is_last = i == pattern.len() - 1;
+        // The path must end exactly where the pattern does
+        if is_last != (part.len() == rest.len()) {
+            return None;
+        }
+        match segment {
+            Segment::Literal(literal) if percent_decode(part) == *literal => {}
+            Segment::Param(name) if !part.is_empty() => params.values.push((name.clone(), percent_decode(part))),
+            _ => return None,
+        }
+        rest = after;
+    }
+    Some(params)
+}
+
+// `%20` and friends; a `%` without two hex digits after it stays as it is
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // `from_str_radix` alone would take a sign too, as in `%+1`
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+
+    fn request(method: &str, target: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.add("GET", "/", |_, _| Response::text(200, "home"));
+        router.add("GET", "/users/:id", |_, params| Response::text(200, &format!("user {}", params.get("id").unwrap())));
+        router.add("DELETE", "/users/:id", |_, _| Response::new(204));
+        router.add("GET", "/users/:id/posts/:post", |_, params| {
+            Response::text(200, &format!("{}/{}", params.get("id").unwrap(), params.get("post").unwrap()))
+        });
+        router.add("GET", "/static/*path", |_, params| Response::text(200, params.get("path").unwrap()));
+        router
+    }
+
+    fn body(response: Response) -> (u16, String) {
+        (response.status, String::from_utf8(response.body.into_bytes().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn literal_and_parameters() {
+        let router = router();
+
+        assert_eq!((200, "home".to_string()), body(router.handle(&request("GET", "/"))));
+        assert_eq!((200, "user 42".to_string()), body(router.handle(&request("GET", "/users/42?full=1"))));
+        assert_eq!((200, "user Ada Lovelace".to_string()), body(router.handle(&request("GET", "/users/Ada%20Lovelace"))));
+        assert_eq!((200, "7/intro".to_string()), body(router.handle(&request("GET", "/users/7/posts/intro"))));
+    }
+
+    #[test]
+    fn wildcard_tails() {
+        let router = router();
+
+        assert_eq!((200, "css/site.css".to_string()), body(router.handle(&request("GET", "/static/css/site.css"))));
+        assert_eq!((200, String::new()), body(router.handle(&request("GET", "/static/"))));
+        assert_eq!(404, router.handle(&request("GET", "/statics/a")).status);
+    }
+
+    #[test]
+    fn not_found_and_not_allowed() {
+        let mut router = router();
+
+        assert_eq!(404, router.handle(&request("GET", "/users")).status);
+        assert_eq!(404, router.handle(&request("GET", "/users/")).status); // an empty parameter
+        assert_eq!(404, router.handle(&request("GET", "/users/42/extra")).status);
+        let response = router.handle(&request("PUT", "/users/42"));
+        assert_eq!((405, Some("GET, DELETE")), (response.status, response.headers.get("Allow")));
+
+        router.not_found(|request, _| Response::html(404, &format!("<h1>{} is gone</h1>", request.path())));
+        assert_eq!((404, "<h1>/nope is gone</h1>".to_string()), body(router.handle(&request("GET", "/nope"))));
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!("a b/c", percent_decode("a%20b%2Fc"));
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!("%zz", percent_decode("%zz"));
+        assert_eq!("%+1 %-1", percent_decode("%+1 %-1"));
+        assert_eq!("é", percent_decode("%C3%A9"));
+    }
+
+    #[test]
+    #[should_panic(expected = "before its end")]
+    fn wildcard_in_the_middle() {
+        Router::new().add("GET", "/*all/more", |_, _| Response::new(200));
+    }
+}
@@ -0,0 +1,250 @@
+// Serves the files below a root directory, the way a route like
+// `/static/*path` hands them out.
+//
+// Nothing outside the root is ever served: `..` segments are refused, and so
+// is a symbolic link that leads out of it. Files are streamed, not read into
+// memory first. A directory is served as its `index.html`, or else as a list
+// of what it holds, if listings are turned on.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::request::Request;
+use crate::response::{Body, Response};
+
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    pub root: PathBuf,
+    pub listings: bool,
+}
+
+impl StaticFiles {
+    /// Serves the files below `root`, without directory listings.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles { root: root.into(), listings: false }
+    }
+
+    /// The response for `path`, relative to the root and already percent-decoded.
+    /// `request` is the request it came with, for the URLs in redirects and listings.
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let mut relative = PathBuf::new();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Response::text(403, "paths with '..' are not served\n"),
+                // A backslash or a drive would be a path separator of its own on Windows
+                _ if segment.contains(['\\', ':', '\0']) => return Response::text(400, "invalid file name\n"),
+                _ => relative.push(segment),
+            }
+        }
+
+        let resolved = match (fs::canonicalize(&self.root), fs::canonicalize(self.root.join(&relative))) {
+            (Ok(root), Ok(resolved)) if resolved.starts_with(&root) => resolved,
+            (Ok(_), Ok(_)) => return Response::text(403, "this file is outside of the served directory\n"),
+            (_, Err(e)) | (Err(e), _) => return error_response(&e),
+        };
+
+        if resolved.is_dir() {
+            // Relative links only work from below the directory
+            if !request.path().ends_with('/') {
+                let mut response = Response::new(301);
+                response.headers.append("Location", &format!("{}/", request.path()));
+                return response;
+            }
+            let index = resolved.join("index.html");
+            if index.is_file() {
+                return file_response(&index);
+            }
+            if !self.listings {
+                return Response::text(403, "directory listings are turned off\n");
+            }
+            return match listing(&resolved, request.path(), !relative.as_os_str().is_empty()) {
+                Ok(html) => Response::html(200, &html),
+                Err(e) => error_response(&e),
+            };
+        }
+        file_response(&resolved)
+    }
+}
+
+fn file_response(path: &Path) -> Response {
+    let opened = File::open(path).and_then(|file| Ok((file.metadata()?.len(), file)));
+    match opened {
+        Ok((length, file)) => {
+            let mut response = Response::new(200);
+            response.headers.append("Content-Type", content_type(path));
+            response.body = Body::Reader(Box::new(file), length);
+            response
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+fn error_response(e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound => Response::text(404, "no such file\n"),
+        io::ErrorKind::PermissionDenied => Response::text(403, "this file can't be read\n"),
+        _ => Response::text(500, &format!("reading the file failed: {e}\n")),
+    }
+}
+
+// The media type for a file, from its extension
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+// An HTML page linking to every entry of `dir`, directories first
+fn listing(dir: &Path, url_path: &str, has_parent: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_dir = entry.file_type()?.is_dir();
+        entries.push((!is_dir, entry.file_name().to_string_lossy().into_owned()));
+    }
+    entries.sort();
+
+    let title = escape_html(url_path);
+    let mut html = format!("<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>Index of {title}</title>\n  </head>\n  <body>\n    <h1>Index of {title}</h1>\n    <ul>\n");
+    if has_parent {
+        html += "      <li><a href=\"../\">../</a></li>\n";
+    }
+    for (is_file, name) in entries {
+        let name = if is_file { name } else { name + "/" };
+        html += &format!("      <li><a href=\"{}\">{}</a></li>\n", escape_html(&percent_encode(&name)), escape_html(&name));
+    }
+    html += "    </ul>\n  </body>\n</html>\n";
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// Enough for a file name to survive as a relative URL
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use std::env;
+
+    // The document root of one test, removed when the test is done with it
+    struct DocRoot(PathBuf);
+
+    impl DocRoot {
+        fn new(test: &str) -> DocRoot {
+            let path = env::temp_dir().join(format!("web-server-static-{test}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            DocRoot(path)
+        }
+
+        fn with_file(self, path: &str, contents: &[u8]) -> DocRoot {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+            self
+        }
+    }
+
+    impl Drop for DocRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get(files: &StaticFiles, url_path: &str) -> Response {
+        let request = Request {
+            method: "GET".to_string(),
+            target: url_path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        };
+        files.serve(&request, url_path.trim_start_matches('/'))
+    }
+
+    #[test]
+    fn files_and_content_types() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let root = DocRoot::new("files").with_file("index.html", b"<h1>hi</h1>").with_file("img/logo.PNG", png).with_file("notes", b"?");
+        let files = StaticFiles::new(&root.0);
+
+        let response = get(&files, "/img/logo.PNG");
+        assert_eq!((200, Some("image/png"), Some(16)), (response.status, response.headers.get("Content-Type"), response.body.length()));
+        assert_eq!(png.to_vec(), response.body.into_bytes().unwrap());
+        assert_eq!(Some("application/octet-stream"), get(&files, "/notes").headers.get("Content-Type"));
+        // A directory is its index
+        let response = get(&files, "/");
+        assert_eq!((200, Some("text/html; charset=utf-8")), (response.status, response.headers.get("Content-Type")));
+        assert_eq!(404, get(&files, "/missing.css").status);
+    }
+
+    #[test]
+    fn traversal_is_refused() {
+        let root = DocRoot::new("traversal").with_file("public/a.txt", b"a").with_file("secret.txt", b"s");
+        let files = StaticFiles::new(root.0.join("public"));
+
+        assert_eq!(200, get(&files, "/a.txt").status);
+        assert_eq!(403, get(&files, "/../secret.txt").status);
+        assert_eq!(403, get(&files, "/./x/../../secret.txt").status);
+        assert_eq!(400, get(&files, "/..\\secret.txt").status);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.0.join("secret.txt"), root.0.join("public/link.txt")).unwrap();
+            assert_eq!(403, get(&files, "/link.txt").status);
+        }
+    }
+
+    #[test]
+    fn directory_listings() {
+        let root = DocRoot::new("listings").with_file("docs/b <&> c.txt", b"").with_file("docs/a/x.txt", b"");
+        let mut files = StaticFiles::new(&root.0);
+
+        assert_eq!(403, get(&files, "/docs/").status);
+        files.listings = true;
+        let response = get(&files, "/docs");
+        assert_eq!((301, Some("/docs/")), (response.status, response.headers.get("Location")));
+        let html = String::from_utf8(get(&files, "/docs/").body.into_bytes().unwrap()).unwrap();
+        assert!(html.contains("<title>Index of /docs/</title>"));
+        assert!(html.contains("<li><a href=\"../\">../</a></li>\n      <li><a href=\"a/\">a/</a></li>\n"));
+        assert!(html.contains("<a href=\"b%20%3C%26%3E%20c.txt\">b &lt;&amp;&gt; c.txt</a>"));
+    }
+}